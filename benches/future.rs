@@ -0,0 +1,130 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compare BumpFuture with `Pin<Box<dyn Future>>` and StackFuture,
+//! for creating and dropping a Future, and a request which creates nested Futures
+//! with a Bump taken from pool and recycled after completed.
+//! run with "cargo bench --bench future"
+use std::{
+    future::Future,
+    hint::black_box,
+    pin::{pin, Pin},
+    task::{Context, Waker},
+    time::{Duration, Instant},
+};
+
+use bump_future::{
+    alloc::PoolBumpAlloc,
+    bump::pool::BumpPool,
+    future::{BumpFuture, BumpFutureExt},
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+use stackfuture::StackFuture;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+/// Future of 256 bytes state, stored in Bump
+fn big_future() -> impl Future<Output = usize> + Send + 'static {
+    let buf = [1_u8; 256];
+    async move { buf.iter().map(|val| *val as usize).sum() }
+}
+
+/// objects created by a batch before the Bump is recycled, so the Bump does not grow unbounded
+const BATCH: u64 = 64;
+
+/// time creating and dropping Futures by `create`, with a Bump taken from pool for every batch,
+/// taking and recycling the Bump is not timed
+fn iter_batched_bump<F, R>(pool: &BumpPool, iters: u64, create: F) -> Duration
+where
+    F: Fn(&PoolBumpAlloc) -> R,
+{
+    let mut total = Duration::ZERO;
+    let mut left = iters;
+    while left > 0 {
+        let batch = left.min(BATCH);
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let start = Instant::now();
+        for _idx in 0..batch {
+            black_box(create(&alloc));
+        }
+        total += start.elapsed();
+        left -= batch;
+    }
+    total
+}
+
+fn bench_create_drop(c: &mut Criterion) {
+    let pool = BumpPool::new(1, 32 * 1024);
+
+    let mut group = c.benchmark_group("create_drop");
+    group.bench_function("bump_future", |b| {
+        b.iter_custom(|iters| iter_batched_bump(&pool, iters, |alloc| big_future().bumped(alloc)))
+    });
+    group.bench_function("box_future", |b| {
+        b.iter(|| black_box(Box::pin(big_future()) as BoxFuture<usize>))
+    });
+    group.bench_function("stack_future", |b| {
+        b.iter(|| black_box(StackFuture::<'static, usize, 512>::from(big_future())))
+    });
+    group.finish();
+}
+
+/// poll to completion, the Futures benched complete on first poll
+fn complete<F: Future>(fut: F) -> F::Output {
+    let mut cx = Context::from_waker(Waker::noop());
+    let fut = pin!(fut);
+    match fut.poll(&mut cx) {
+        std::task::Poll::Ready(val) => val,
+        std::task::Poll::Pending => unreachable!("benched Future should be ready"),
+    }
+}
+
+/// request handler returns BumpFuture, nested 3 levels
+fn bump_handler(alloc: &PoolBumpAlloc, depth: usize) -> BumpFuture<usize> {
+    if depth == 0 {
+        return big_future().bumped(alloc);
+    }
+    let inner = bump_handler(alloc, depth - 1);
+    let buf = [1_u8; 64];
+    async move { inner.await + buf.len() }.bumped(alloc)
+}
+/// request handler returns BoxFuture, nested 3 levels
+fn box_handler(depth: usize) -> BoxFuture<usize> {
+    if depth == 0 {
+        return Box::pin(big_future());
+    }
+    let inner = box_handler(depth - 1);
+    let buf = [1_u8; 64];
+    Box::pin(async move { inner.await + buf.len() })
+}
+
+fn bench_request(c: &mut Criterion) {
+    let pool = BumpPool::new(16, 4096);
+
+    let mut group = c.benchmark_group("request");
+    group.bench_function("bump_future", |b| {
+        b.iter(|| {
+            // take Bump from pool, and recycled when all Futures dropped
+            let alloc = PoolBumpAlloc::new(pool.take());
+            let fut = bump_handler(&alloc, 3);
+            drop(alloc);
+            black_box(complete(fut))
+        })
+    });
+    group.bench_function("box_future", |b| {
+        b.iter(|| black_box(complete(box_handler(3))))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_create_drop, bench_request);
+criterion_main!(benches);
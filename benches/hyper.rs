@@ -0,0 +1,106 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Request loop over a loopback keep-alive connection, the server serves requests
+//! with BumpFuture scoped in the Bump of connection, or with BoxFuture,
+//! reproducible replacement of the manual rewrk run of hyper_server example.
+//! run with "cargo bench --bench hyper --features hyper"
+use std::{convert::Infallible, future::Future, net::SocketAddr, pin::Pin};
+
+use bump_future::{bump::pool::PoolConfig, server::serve_connection};
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, Criterion};
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::Incoming, client::conn::http1::SendRequest, server::conn::http1, service::service_fn,
+    Request, Response,
+};
+use hyper_util::rt::TokioIo;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+};
+
+bump_future::alloc_mod!(bench_alloc);
+
+type ServiceResult = Result<Response<Full<Bytes>>, Infallible>;
+
+/// response built by nested Futures, stored in Bump of request
+async fn serve_bump(_req: Request<Incoming>) -> ServiceResult {
+    let body = bench_alloc::bumped(async move {
+        let buf = [b'a'; 128];
+        Bytes::copy_from_slice(&buf)
+    });
+    Ok(Response::new(Full::new(body.await)))
+}
+
+/// response built by nested Futures, stored on heap
+fn serve_box(_req: Request<Incoming>) -> Pin<Box<dyn Future<Output = ServiceResult> + Send>> {
+    Box::pin(async move {
+        let body: Pin<Box<dyn Future<Output = Bytes> + Send>> = Box::pin(async move {
+            let buf = [b'a'; 128];
+            Bytes::copy_from_slice(&buf)
+        });
+        Ok(Response::new(Full::new(body.await)))
+    })
+}
+
+/// start server on loopback, and return a client of keep-alive connection
+async fn connect(bump: bool) -> SendRequest<Full<Bytes>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (tcp, _) = listener.accept().await.unwrap();
+        let io = TokioIo::new(tcp);
+        let builder = http1::Builder::new();
+        let _ = if bump {
+            serve_connection(&builder, io, bench_alloc::Scope, serve_bump).await
+        } else {
+            builder.serve_connection(io, service_fn(serve_box)).await
+        };
+    });
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let (sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(tcp))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+    sender
+}
+
+async fn request(sender: &mut SendRequest<Full<Bytes>>) -> usize {
+    sender.ready().await.unwrap();
+    let resp = sender
+        .send_request(Request::new(Full::new(Bytes::new())))
+        .await
+        .unwrap();
+    resp.into_body().collect().await.unwrap().to_bytes().len()
+}
+
+fn bench_hyper(c: &mut Criterion) {
+    let conf = PoolConfig::new(64, 1024);
+    bench_alloc::init(conf).unwrap();
+    let rt = Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("hyper");
+    for (name, bump) in [("bump_future", true), ("box_future", false)] {
+        let sender = rt.block_on(connect(bump));
+        let sender = tokio::sync::Mutex::new(sender);
+        group.bench_function(name, |b| {
+            b.to_async(&rt)
+                .iter(|| async { request(&mut *sender.lock().await).await })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_hyper);
+criterion_main!(benches);
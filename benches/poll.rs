@@ -0,0 +1,61 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compare per-poll overhead of BumpFuture with `Pin<Box<dyn Future>>` and StackFuture,
+//! the future polled is always pending, so only dispatch cost is measured.
+//! run with "cargo bench --bench poll"
+use std::{
+    future::Future,
+    hint::black_box,
+    pin::{pin, Pin},
+    task::{Context, Poll, Waker},
+};
+
+use bump_future::{alloc::PoolBumpAlloc, bump::pool::BumpPool, future::BumpFutureExt};
+use criterion::{criterion_group, criterion_main, Criterion};
+use stackfuture::StackFuture;
+
+/// count polls and never complete, N words of state
+struct Pending<const N: usize>([u64; N]);
+
+impl<const N: usize> Future for Pending<N> {
+    type Output = u64;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0[0] += 1;
+        Poll::Pending
+    }
+}
+
+fn bench_poll(c: &mut Criterion) {
+    let pool = BumpPool::new(1, 1024);
+    let alloc = PoolBumpAlloc::new(pool.take());
+    let mut cx = Context::from_waker(Waker::noop());
+
+    let mut group = c.benchmark_group("poll");
+    let mut fut = Pending([0; 16]).bumped(&alloc);
+    group.bench_function("bump_future", |b| {
+        b.iter(|| black_box(Pin::new(&mut fut).poll(&mut cx)))
+    });
+    let mut fut: Pin<Box<dyn Future<Output = u64> + Send>> = Box::pin(Pending([0; 16]));
+    group.bench_function("box_future", |b| {
+        b.iter(|| black_box(fut.as_mut().poll(&mut cx)))
+    });
+    let mut fut = pin!(StackFuture::<'static, u64, 256>::from(Pending([0; 16])));
+    group.bench_function("stack_future", |b| {
+        b.iter(|| black_box(fut.as_mut().poll(&mut cx)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_poll);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compare taking a Bump from BumpPool and recycling it, with creating
+//! and dropping a Bump on system allocator every time.
+//! run with "cargo bench --bench pool"
+use std::hint::black_box;
+
+use bump_future::{
+    alloc::{BumpAlloc, PoolBumpAlloc},
+    bump::pool::BumpPool,
+    obj::UnsafeObject,
+};
+use bumpalo::Bump;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn bench_pool(c: &mut Criterion) {
+    let pool = BumpPool::new(16, 4096);
+
+    let mut group = c.benchmark_group("pool");
+    group.bench_function("take_recycle", |b| {
+        b.iter(|| {
+            let bump = pool.take();
+            black_box(bump.alloc([1_u8; 256]));
+            drop(bump);
+        })
+    });
+    group.bench_function("alloc_take_recycle", |b| {
+        b.iter(|| {
+            // recycled when both allocator and object dropped
+            let alloc = PoolBumpAlloc::new(pool.take());
+            black_box(alloc.alloc_typed([1_u8; 256]));
+        })
+    });
+    group.bench_function("system_bump", |b| {
+        b.iter(|| {
+            let bump = Bump::with_capacity(4096);
+            black_box(bump.alloc([1_u8; 256]));
+            drop(bump);
+        })
+    });
+    group.bench_function("system_box", |b| {
+        b.iter(|| black_box(UnsafeObject::new_boxed([1_u8; 256])))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_pool);
+criterion_main!(benches);
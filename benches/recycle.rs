@@ -0,0 +1,70 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compare recycling a Bump by reference count inline with the previous design,
+//! which spawns a task per request to wait all references (mpsc Sender) dropped.
+//! run with "cargo bench --bench recycle"
+use std::future::{ready, Ready};
+
+use bump_future::{
+    alloc::PoolBumpAlloc, bump::pool::BumpPool, future::BumpFutureExt, obj::UnsafeObject,
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+use tokio::{runtime::Handle, sync::mpsc};
+
+/// current design, the last of allocator and BumpRef dropped recycles the Bump
+async fn refcount_request(pool: &BumpPool) -> u32 {
+    let alloc = PoolBumpAlloc::new(pool.take());
+    let fut = ready(1_u32).bumped(&alloc);
+    drop(alloc);
+    fut.await
+}
+
+/// previous design, every object holds a Sender clone, and the allocator
+/// spawns a task to wait the channel closed before recycle the Bump
+async fn mpsc_request(pool: &BumpPool, handle: &Handle) -> u32 {
+    let bump = pool.take();
+    let (tx, mut rx) = mpsc::channel::<()>(1);
+    let mut obj = unsafe { UnsafeObject::new(&bump, ready(1_u32)) };
+    let bump_ref = tx.clone();
+    let fut = unsafe { obj.downcast_mut::<Ready<u32>>() }.expect("type mismatch");
+    let rslt = fut.await;
+    drop(obj);
+    drop(bump_ref);
+    handle.spawn(async move {
+        drop(tx);
+        rx.recv().await;
+        drop(bump);
+    });
+    rslt
+}
+
+fn bench_recycle(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .build()
+        .unwrap();
+    let pool = BumpPool::new(1024, 1024);
+    let handle = rt.handle().clone();
+
+    let mut group = c.benchmark_group("recycle");
+    group.bench_function("refcount_inline", |b| {
+        b.to_async(&rt).iter(|| refcount_request(&pool))
+    });
+    group.bench_function("mpsc_spawn", |b| {
+        b.to_async(&rt).iter(|| mpsc_request(&pool, &handle))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_recycle);
+criterion_main!(benches);
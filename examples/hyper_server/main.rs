@@ -23,9 +23,11 @@ use std::convert::Infallible;
 use std::future::Future;
 use std::net::SocketAddr;
 use std::pin::Pin;
+use std::time::Duration;
 
 use bump_future::bump::pool::PoolConfig;
 use bump_future::future::{BumpFuture, BumpFutureExt};
+use bump_future::server::serve_connection;
 use bytes::Bytes;
 use clap::Parser;
 use http_body_util::Full;
@@ -35,35 +37,23 @@ use hyper::service::{service_fn, Service};
 use hyper::{Request, Response};
 use hyper_util::rt::{TokioIo, TokioTimer};
 use tokio::net::TcpListener;
-use tokio_util::either::Either;
 
 bump_future::alloc_mod!(bump_alloc);
 
 type ServiceResult = Result<Response<Full<Bytes>>, Infallible>;
 
-/// serve request with either BoxService or BumpService
-fn serve_request(
-    bump: bool,
-    req: Request<Incoming>,
-) -> impl Future<Output = ServiceResult> + Send + 'static {
-    if bump {
-        let msg = Msg::text("Hello World! This message from BumpFuture");
-        let fut = async move {
-            let svc = BumpService(msg);
-            let rslt = svc.call(req).await;
-            rslt
-        };
-        // every Request processing use a Bump and released after processed
-        return Either::Left(bump_alloc::set_bump(fut));
-    } else {
-        let msg = Msg::text("Hello World! This message from BoxFuture");
-        let fut = async move {
-            let svc = BoxService(msg);
-            let rslt = svc.call(req).await;
-            rslt
-        };
-        return Either::Right(fut);
-    }
+/// serve request with BoxService
+fn serve_box(req: Request<Incoming>) -> impl Future<Output = ServiceResult> + Send + 'static {
+    let msg = Msg::text("Hello World! This message from BoxFuture");
+    let svc = BoxService(msg);
+    return svc.call(req);
+}
+
+/// serve request with BumpService, called inside the Bump scope of request
+fn serve_bump(req: Request<Incoming>) -> impl Future<Output = ServiceResult> + Send + 'static {
+    let msg = Msg::text("Hello World! This message from BumpFuture");
+    let svc = BumpService(msg);
+    return svc.call(req);
 }
 
 struct Msg(pub Bytes);
@@ -121,12 +111,13 @@ struct Cli {
 
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // pre allocate memory for BumFuture use
-    let conf = PoolConfig {
-        pool_capacity: 1024 * 100,
-        bump_capacity: 1024,
-    };
+    // pre allocate memory for BumFuture use, the pool grows on demand and
+    // idle Bump instances beyond min_idle are released by the trimmer
+    let conf = PoolConfig::new(1024 * 100, 1024)
+        .with_min_idle(Some(1024))
+        .with_idle_timeout(Some(Duration::from_secs(30)));
     bump_alloc::init(conf).unwrap();
+    bump_alloc::spawn_trimmer(Duration::from_secs(10));
 
     let cli = Cli::parse();
     let bump = cli.bump;
@@ -138,17 +129,16 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     loop {
         let (tcp, _) = listener.accept().await?;
         let io = TokioIo::new(tcp);
-
         tokio::task::spawn(async move {
-            if let Err(err) = http1::Builder::new()
-                .serve_connection(
-                    io,
-                    service_fn(move |req: Request<Incoming>| {
-                        return serve_request(bump, req);
-                    }),
-                )
-                .await
-            {
+            let builder = http1::Builder::new();
+            let rslt = if bump {
+                // one Bump for each keep-alive connection, every Request processing use it,
+                // and reset after processed
+                serve_connection(&builder, io, bump_alloc::Scope, serve_bump).await
+            } else {
+                builder.serve_connection(io, service_fn(serve_box)).await
+            };
+            if let Err(err) = rslt {
                 println!("Error serving connection: {:?}", err);
             }
         });
@@ -10,67 +10,509 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! [`BumpAlloc`] trait and implemention [`TokioBumpAlloc`]
+//! [`BumpAlloc`] trait and implemention [`PoolBumpAlloc`], [`HeapAlloc`],
+//! and [`LocalBumpAlloc`] for !Send objects
+//!
+//! With `tokio` feature, [`TokioBumpAlloc`] wraps PoolBumpAlloc for tokio task local
+//!
+//! With `allocator-api2` feature, [`BumpAllocator`] implements `allocator_api2::alloc::Allocator`
+use std::{
+    alloc::{handle_alloc_error, Layout},
+    cell::OnceCell,
+    ptr::NonNull,
+    rc::Rc,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
 use bumpalo::Bump;
-use tokio::runtime::Handle;
 
 use crate::{
-    bump::{BumpRef, BumpRefMgr, RecycleableBump},
-    obj::{BumpObject, UnsafeObject},
+    boxed::{BumpBox, LocalBumpBox},
+    bump::{pool::LimitPolicy, BumpRef, LocalBumpRef, RecycleableBump},
+    error::{AllocError, AllocErrorKind},
+    obj::{alloc_with_policy, try_alloc_layout, BumpObject, LocalBumpObject, UnsafeObject},
 };
 
+#[cfg(feature = "allocator-api2")]
+mod api2;
+#[cfg(feature = "allocator-api2")]
+pub use api2::BumpAllocator;
+
+/// Lock shared Bump, poisoned lock is fine because Bump is always consistent
+#[inline]
+pub(crate) fn lock_bump(lock: &Mutex<()>) -> MutexGuard<'_, ()> {
+    lock.lock().unwrap_or_else(|err| err.into_inner())
+}
+
 /// BumpObject alloc trait
 pub trait BumpAlloc {
-    /// alloc a BumpObject in the Bump managed
+    /// alloc a BumpObject in the Bump managed,
+    /// panic when allocation limit exceeded with [`LimitPolicy::Error`], use [`BumpAlloc::try_alloc`] to handle it
     fn alloc<T>(&self, val: T) -> BumpObject
     where
         T: Send + 'static;
+
+    /// alloc a BumpObject in the Bump managed,
+    /// return AllocError instead of abort when allocation failed
+    fn try_alloc<T>(&self, val: T) -> Result<BumpObject, AllocError>
+    where
+        T: Send + 'static;
+
+    /// alloc a typed BumpBox in the Bump managed, access it without downcast,
+    /// panic when allocation limit exceeded with [`LimitPolicy::Error`]
+    fn alloc_typed<T>(&self, val: T) -> BumpBox<T>
+    where
+        T: Send + 'static;
+
+    /// alloc a typed BumpBox in the Bump managed,
+    /// return AllocError instead of abort when allocation failed
+    fn try_alloc_typed<T>(&self, val: T) -> Result<BumpBox<T>, AllocError>
+    where
+        T: Send + 'static;
+
+    /// alloc raw memory of layout in the Bump managed, and a BumpRef to keep it alive,
+    /// return None when it should be allocated on global heap instead,
+    /// panic when allocation limit exceeded with [`LimitPolicy::Error`]
+    fn alloc_raw(&self, layout: Layout) -> Option<(NonNull<u8>, BumpRef)>;
 }
 
-/// Allocate object in Bump within async task
-/// when dropped ,it will spawn a task to wait all BumpRef dropped.
-/// when all BumpRef dropped,it will drop RecycleableBump,and the Bump will be
-/// reset and release back to pool
-pub struct TokioBumpAlloc {
-    handle: Handle,
-    bump: Option<RecycleableBump>,
-    ref_mgr: Option<BumpRefMgr>,
+/// Allocate object in Bump taken from a BumpPool, works with any executor or none
+/// every BumpObject allocated hold a reference count of the Bump,
+/// when this allocator and all BumpObject dropped, the last one dropped will recycle
+/// the Bump according to the [`RecycleStrategy`](crate::bump::recycle::RecycleStrategy) of pool.
+pub struct PoolBumpAlloc {
+    bump: RecycleableBump,
+    // shared with BumpAllocator handles once created, then every allocation must hold it
+    lock: OnceCell<Arc<Mutex<()>>>,
 }
 
-impl TokioBumpAlloc {
-    pub fn new(handle: Handle, bump: RecycleableBump) -> Self {
+impl PoolBumpAlloc {
+    pub fn new(bump: RecycleableBump) -> Self {
         Self {
-            handle,
-            bump: Some(bump),
-            ref_mgr: Some(BumpRefMgr::new()),
+            bump,
+            lock: OnceCell::new(),
         }
     }
     #[inline]
-    fn bump(&self) -> &Bump {
-        self.bump.as_ref().unwrap()
+    fn bump(&self) -> &RecycleableBump {
+        &self.bump
+    }
+    /// Lock the Bump when it is shared with BumpAllocator handles,
+    /// handles are created on this thread, so no handle exists if lock not set
+    #[inline]
+    fn guard(&self) -> Option<MutexGuard<'_, ()>> {
+        self.lock.get().map(|lock| lock_bump(lock))
     }
     #[inline]
     fn new_bump_ref(&self) -> BumpRef {
-        self.ref_mgr.as_ref().unwrap().new_ref()
+        self.bump.new_ref()
+    }
+    /// Like [`BumpAlloc::alloc_raw`], but return AllocError instead of panic or abort
+    pub(crate) fn try_alloc_raw(
+        &self,
+        layout: Layout,
+    ) -> Result<Option<(NonNull<u8>, BumpRef)>, AllocError> {
+        let _guard = self.guard();
+        try_alloc_raw_in(self.bump(), self.bump.limit_policy(), layout, || {
+            self.new_bump_ref()
+        })
+    }
+    /// Count an object allocated on global heap instead of the Bump, in `heap_fallbacks` of the pool
+    pub(crate) fn record_heap_fallback(&self) {
+        self.bump.record_heap_fallback();
+    }
+}
+impl BumpAlloc for PoolBumpAlloc {
+    fn alloc<T>(&self, val: T) -> BumpObject
+    where
+        T: Send + 'static,
+    {
+        let _guard = self.guard();
+        alloc_in(self.bump(), self.bump.limit_policy(), val, || {
+            self.new_bump_ref()
+        })
+    }
+
+    fn try_alloc<T>(&self, val: T) -> Result<BumpObject, AllocError>
+    where
+        T: Send + 'static,
+    {
+        let _guard = self.guard();
+        try_alloc_in(self.bump(), self.bump.limit_policy(), val, || {
+            self.new_bump_ref()
+        })
+    }
+
+    fn alloc_typed<T>(&self, val: T) -> BumpBox<T>
+    where
+        T: Send + 'static,
+    {
+        let _guard = self.guard();
+        alloc_typed_in(self.bump(), self.bump.limit_policy(), val, || {
+            self.new_bump_ref()
+        })
+    }
+
+    fn try_alloc_typed<T>(&self, val: T) -> Result<BumpBox<T>, AllocError>
+    where
+        T: Send + 'static,
+    {
+        let _guard = self.guard();
+        try_alloc_typed_in(self.bump(), self.bump.limit_policy(), val, || {
+            self.new_bump_ref()
+        })
+    }
+
+    fn alloc_raw(&self, layout: Layout) -> Option<(NonNull<u8>, BumpRef)> {
+        let _guard = self.guard();
+        alloc_raw_in(self.bump(), self.bump.limit_policy(), layout, || {
+            self.new_bump_ref()
+        })
+    }
+}
+impl Drop for PoolBumpAlloc {
+    fn drop(&mut self) {
+        self.bump.mark_released();
+    }
+}
+
+/// PoolBumpAlloc used within tokio task, the allocator of `TASK_ALLOC` generated by [`alloc_mod!`](crate::alloc_mod),
+/// it no longer depends on tokio runtime, the Bump is recycled by the last reference dropped
+#[cfg(feature = "tokio")]
+pub struct TokioBumpAlloc {
+    inner: PoolBumpAlloc,
+}
+#[cfg(feature = "tokio")]
+impl TokioBumpAlloc {
+    #[deprecated(
+        note = "handle is not used any more, use `TokioBumpAlloc::from(PoolBumpAlloc::new(bump))`"
+    )]
+    pub fn new(_handle: tokio::runtime::Handle, bump: RecycleableBump) -> Self {
+        Self::from(PoolBumpAlloc::new(bump))
+    }
+    /// Unwrap the PoolBumpAlloc
+    pub fn into_inner(self) -> PoolBumpAlloc {
+        self.inner
+    }
+}
+#[cfg(feature = "tokio")]
+impl From<PoolBumpAlloc> for TokioBumpAlloc {
+    fn from(inner: PoolBumpAlloc) -> Self {
+        Self { inner }
     }
 }
+#[cfg(feature = "tokio")]
+impl std::ops::Deref for TokioBumpAlloc {
+    type Target = PoolBumpAlloc;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+#[cfg(feature = "tokio")]
 impl BumpAlloc for TokioBumpAlloc {
-    fn alloc<T>(&self, val: T) -> crate::obj::BumpObject
+    #[inline]
+    fn alloc<T>(&self, val: T) -> BumpObject
+    where
+        T: Send + 'static,
+    {
+        self.inner.alloc(val)
+    }
+
+    #[inline]
+    fn try_alloc<T>(&self, val: T) -> Result<BumpObject, AllocError>
+    where
+        T: Send + 'static,
+    {
+        self.inner.try_alloc(val)
+    }
+
+    #[inline]
+    fn alloc_typed<T>(&self, val: T) -> BumpBox<T>
+    where
+        T: Send + 'static,
+    {
+        self.inner.alloc_typed(val)
+    }
+
+    #[inline]
+    fn try_alloc_typed<T>(&self, val: T) -> Result<BumpBox<T>, AllocError>
+    where
+        T: Send + 'static,
+    {
+        self.inner.try_alloc_typed(val)
+    }
+
+    #[inline]
+    fn alloc_raw(&self, layout: Layout) -> Option<(NonNull<u8>, BumpRef)> {
+        self.inner.alloc_raw(layout)
+    }
+}
+
+/// Allocate object on global heap,used as fallback when no Bump available
+/// so code can always return BumpFuture whether a Bump is set or not
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapAlloc;
+
+impl BumpAlloc for HeapAlloc {
+    fn alloc<T>(&self, val: T) -> BumpObject
+    where
+        T: Send + 'static,
+    {
+        BumpObject::new_boxed(val)
+    }
+
+    fn try_alloc<T>(&self, val: T) -> Result<BumpObject, AllocError>
+    where
+        T: Send + 'static,
+    {
+        Ok(BumpObject::new_boxed(val))
+    }
+
+    fn alloc_typed<T>(&self, val: T) -> BumpBox<T>
     where
         T: Send + 'static,
     {
-        let inner = unsafe { UnsafeObject::new(self.bump(), val) };
-        let bump_ref = self.new_bump_ref();
-        BumpObject::new(inner, bump_ref)
+        BumpBox::new_boxed(val)
+    }
+
+    fn try_alloc_typed<T>(&self, val: T) -> Result<BumpBox<T>, AllocError>
+    where
+        T: Send + 'static,
+    {
+        Ok(BumpBox::new_boxed(val))
+    }
+
+    fn alloc_raw(&self, _layout: Layout) -> Option<(NonNull<u8>, BumpRef)> {
+        None
+    }
+}
+
+/// Allocate object which may be !Send in Bump,used within current thread runtime or LocalSet
+/// It is !Send, every LocalBumpObject allocated hold a reference count of the Bump,
+/// when this allocator and all LocalBumpObject dropped,the Bump will be reset and
+/// recycled according to the [`RecycleStrategy`](crate::bump::recycle::RecycleStrategy) of pool.
+pub struct LocalBumpAlloc {
+    bump: Rc<RecycleableBump>,
+}
+
+impl LocalBumpAlloc {
+    pub fn new(bump: RecycleableBump) -> Self {
+        Self {
+            bump: Rc::new(bump),
+        }
+    }
+    /// alloc a LocalBumpObject in the Bump managed
+    pub fn alloc<T>(&self, val: T) -> LocalBumpObject
+    where
+        T: 'static,
+    {
+        let inner = unsafe { new_object(&self.bump, self.bump.limit_policy(), val) };
+        let bump_ref = LocalBumpRef::new(self.bump.clone());
+        LocalBumpObject::new(inner, bump_ref)
+    }
+    /// alloc a typed LocalBumpBox in the Bump managed, access it without downcast
+    pub fn alloc_typed<T>(&self, val: T) -> LocalBumpBox<T>
+    where
+        T: 'static,
+    {
+        let (ptr, boxed) = unsafe { new_typed(&self.bump, self.bump.limit_policy(), val) };
+        let bump_ref = (!boxed).then(|| LocalBumpRef::new(self.bump.clone()));
+        unsafe { BumpBox::from_raw(ptr, bump_ref) }
     }
 }
-impl Drop for TokioBumpAlloc {
+impl Drop for LocalBumpAlloc {
     fn drop(&mut self) {
-        let bump = self.bump.take().expect("should not be None");
-        let ref_mgr = self.ref_mgr.take().expect("should not be None");
-        self.handle.spawn(async move {
-            ref_mgr.wait_no_ref().await;
-            drop(bump);
-        });
+        self.bump.mark_released();
+    }
+}
+
+/// allocate BumpObject in Bump, new_ref create the BumpRef of it
+#[inline]
+pub(crate) fn alloc_in<T>(
+    bump: &Bump,
+    policy: LimitPolicy,
+    val: T,
+    new_ref: impl FnOnce() -> BumpRef,
+) -> BumpObject
+where
+    T: Send + 'static,
+{
+    let inner = unsafe { new_object(bump, policy, val) };
+    match inner.is_boxed() {
+        true => BumpObject::from_parts(inner, None),
+        false => BumpObject::new(inner, new_ref()),
+    }
+}
+
+#[inline]
+pub(crate) fn try_alloc_in<T>(
+    bump: &Bump,
+    policy: LimitPolicy,
+    val: T,
+    new_ref: impl FnOnce() -> BumpRef,
+) -> Result<BumpObject, AllocError>
+where
+    T: Send + 'static,
+{
+    let inner = unsafe { UnsafeObject::try_new_with_policy(bump, val, policy)? };
+    match inner.is_boxed() {
+        true => Ok(BumpObject::from_parts(inner, None)),
+        false => Ok(BumpObject::new(inner, new_ref())),
+    }
+}
+
+#[inline]
+pub(crate) fn alloc_typed_in<T>(
+    bump: &Bump,
+    policy: LimitPolicy,
+    val: T,
+    new_ref: impl FnOnce() -> BumpRef,
+) -> BumpBox<T>
+where
+    T: Send + 'static,
+{
+    let (ptr, boxed) = unsafe { new_typed(bump, policy, val) };
+    let bump_ref = (!boxed).then(new_ref);
+    unsafe { BumpBox::from_raw(ptr, bump_ref) }
+}
+
+#[inline]
+pub(crate) fn try_alloc_typed_in<T>(
+    bump: &Bump,
+    policy: LimitPolicy,
+    val: T,
+    new_ref: impl FnOnce() -> BumpRef,
+) -> Result<BumpBox<T>, AllocError>
+where
+    T: Send + 'static,
+{
+    let (ptr, boxed) = unsafe { alloc_with_policy(bump, val, policy)? };
+    let bump_ref = (!boxed).then(new_ref);
+    Ok(unsafe { BumpBox::from_raw(ptr, bump_ref) })
+}
+
+#[inline]
+pub(crate) fn alloc_raw_in(
+    bump: &Bump,
+    policy: LimitPolicy,
+    layout: Layout,
+    new_ref: impl FnOnce() -> BumpRef,
+) -> Option<(NonNull<u8>, BumpRef)> {
+    match try_alloc_raw_in(bump, policy, layout, new_ref) {
+        Ok(rslt) => rslt,
+        Err(err) if err.kind() == AllocErrorKind::LimitExceeded => panic!("{}", err),
+        Err(err) => handle_alloc_error(err.layout()),
+    }
+}
+
+/// Same as [`alloc_raw_in`], but return AllocError instead of panic or abort
+#[inline]
+pub(crate) fn try_alloc_raw_in(
+    bump: &Bump,
+    policy: LimitPolicy,
+    layout: Layout,
+    new_ref: impl FnOnce() -> BumpRef,
+) -> Result<Option<(NonNull<u8>, BumpRef)>, AllocError> {
+    match try_alloc_layout(bump, layout) {
+        Ok(ptr) => Ok(Some((ptr, new_ref()))),
+        Err(err) if err.kind() == AllocErrorKind::LimitExceeded && policy == LimitPolicy::Heap => {
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// allocate object in Bump according to the limit policy,
+/// panic when allocation limit exceeded, abort when out of memory
+/// # Safety
+/// Same as [`UnsafeObject::new_local`]
+unsafe fn new_object<T>(bump: &Bump, policy: LimitPolicy, val: T) -> UnsafeObject
+where
+    T: 'static,
+{
+    let (ptr, boxed) = new_typed(bump, policy, val);
+    UnsafeObject::from_raw(ptr, boxed)
+}
+
+/// Same as [`new_object`], but return the typed pointer and if it is allocated on global heap
+/// # Safety
+/// Same as [`UnsafeObject::new_local`]
+unsafe fn new_typed<T>(bump: &Bump, policy: LimitPolicy, val: T) -> (NonNull<T>, bool)
+where
+    T: 'static,
+{
+    match alloc_with_policy(bump, val, policy) {
+        Ok(rslt) => rslt,
+        Err(err) if err.kind() == AllocErrorKind::LimitExceeded => panic!("{}", err),
+        Err(err) => handle_alloc_error(err.layout()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bump::pool::{BumpPool, LimitPolicy, PoolConfig},
+        error::AllocErrorKind,
+    };
+
+    use super::{BumpAlloc, PoolBumpAlloc};
+
+    fn limited_pool(limit_policy: LimitPolicy) -> BumpPool {
+        let conf = PoolConfig::new(1, 1024)
+            .with_max_bump_bytes(Some(2048))
+            .with_limit_policy(limit_policy);
+        BumpPool::with_config(&conf)
+    }
+
+    #[tokio::test]
+    async fn test_limit_error() {
+        let pool = limited_pool(LimitPolicy::Error);
+        let alloc = PoolBumpAlloc::new(pool.take());
+
+        let obj = alloc.try_alloc([0_u8; 256]).unwrap();
+        assert!(!obj.is_boxed());
+
+        let err = alloc.try_alloc([0_u8; 4096]).err().expect("should fail");
+        assert_eq!(err.kind(), AllocErrorKind::LimitExceeded);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "allocation limit exceeded")]
+    async fn test_limit_error_panic() {
+        let pool = limited_pool(LimitPolicy::Error);
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let _obj = alloc.alloc([0_u8; 4096]);
+    }
+
+    #[tokio::test]
+    async fn test_limit_heap() {
+        let pool = limited_pool(LimitPolicy::Heap);
+        let alloc = PoolBumpAlloc::new(pool.take());
+
+        let obj = alloc.alloc([0_u8; 256]);
+        assert!(!obj.is_boxed());
+
+        let obj = alloc.alloc([1_u8; 4096]);
+        assert!(obj.is_boxed());
+        let obj = alloc.try_alloc([2_u8; 4096]).unwrap();
+        assert!(obj.is_boxed());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn test_tokio_alloc() {
+        use super::TokioBumpAlloc;
+
+        let pool = BumpPool::new(1, 1024);
+        let alloc = TokioBumpAlloc::new(tokio::runtime::Handle::current(), pool.take());
+        let obj = alloc.alloc([0_u8; 256]);
+        assert!(!obj.is_boxed());
+        drop(alloc);
+        assert_eq!(pool.len(), 0);
+        // recycled when the last reference dropped, no task spawned
+        drop(obj);
+        assert_eq!(pool.len(), 1);
     }
 }
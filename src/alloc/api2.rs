@@ -0,0 +1,194 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [`BumpAllocator`] handle implements [`Allocator`] of allocator_api2
+use std::{
+    alloc::Layout,
+    ptr::NonNull,
+    sync::{Arc, Mutex},
+};
+
+use allocator_api2::alloc::{AllocError as ApiAllocError, Allocator};
+
+use crate::{boxed::BumpBox, bump::BumpRef, error::AllocError, obj::BumpObject};
+
+use super::{
+    alloc_in, alloc_raw_in, alloc_typed_in, lock_bump, try_alloc_in, try_alloc_typed_in, BumpAlloc,
+    PoolBumpAlloc,
+};
+
+/// Cloneable and Send allocator handle of the Bump managed by a PoolBumpAlloc,
+/// usable with allocator_api2 collections, e.g. `hashbrown::HashMap<K, V, BumpAllocator>`
+/// Every clone holds a BumpRef, so the Bump will not be reset until all clones dropped.
+/// Bump is !Sync, so once a handle created, allocations of the PoolBumpAlloc and all handles are serialized by a lock.
+/// Allocation exceed the limit of Bump fails with AllocError, whatever the limit policy is
+#[derive(Clone)]
+pub struct BumpAllocator {
+    bump_ref: BumpRef,
+    lock: Arc<Mutex<()>>,
+}
+
+impl PoolBumpAlloc {
+    /// Create an allocator handle of the Bump managed
+    pub fn allocator(&self) -> BumpAllocator {
+        let lock = self.lock.get_or_init(Arc::default).clone();
+        BumpAllocator {
+            bump_ref: self.new_bump_ref(),
+            lock,
+        }
+    }
+}
+
+unsafe impl Allocator for BumpAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, ApiAllocError> {
+        let _guard = lock_bump(&self.lock);
+        unsafe { self.bump_ref.bump() }.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let _guard = lock_bump(&self.lock);
+        self.bump_ref.bump().deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, ApiAllocError> {
+        let _guard = lock_bump(&self.lock);
+        self.bump_ref.bump().grow(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, ApiAllocError> {
+        let _guard = lock_bump(&self.lock);
+        self.bump_ref
+            .bump()
+            .grow_zeroed(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, ApiAllocError> {
+        let _guard = lock_bump(&self.lock);
+        self.bump_ref.bump().shrink(ptr, old_layout, new_layout)
+    }
+}
+
+impl BumpAlloc for BumpAllocator {
+    fn alloc<T>(&self, val: T) -> BumpObject
+    where
+        T: Send + 'static,
+    {
+        let _guard = lock_bump(&self.lock);
+        let bump = unsafe { self.bump_ref.bump() };
+        alloc_in(bump, self.bump_ref.limit_policy(), val, || {
+            self.bump_ref.clone()
+        })
+    }
+
+    fn try_alloc<T>(&self, val: T) -> Result<BumpObject, AllocError>
+    where
+        T: Send + 'static,
+    {
+        let _guard = lock_bump(&self.lock);
+        let bump = unsafe { self.bump_ref.bump() };
+        try_alloc_in(bump, self.bump_ref.limit_policy(), val, || {
+            self.bump_ref.clone()
+        })
+    }
+
+    fn alloc_typed<T>(&self, val: T) -> BumpBox<T>
+    where
+        T: Send + 'static,
+    {
+        let _guard = lock_bump(&self.lock);
+        let bump = unsafe { self.bump_ref.bump() };
+        alloc_typed_in(bump, self.bump_ref.limit_policy(), val, || {
+            self.bump_ref.clone()
+        })
+    }
+
+    fn try_alloc_typed<T>(&self, val: T) -> Result<BumpBox<T>, AllocError>
+    where
+        T: Send + 'static,
+    {
+        let _guard = lock_bump(&self.lock);
+        let bump = unsafe { self.bump_ref.bump() };
+        try_alloc_typed_in(bump, self.bump_ref.limit_policy(), val, || {
+            self.bump_ref.clone()
+        })
+    }
+
+    fn alloc_raw(&self, layout: Layout) -> Option<(NonNull<u8>, BumpRef)> {
+        let _guard = lock_bump(&self.lock);
+        let bump = unsafe { self.bump_ref.bump() };
+        alloc_raw_in(bump, self.bump_ref.limit_policy(), layout, || {
+            self.bump_ref.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        alloc::{BumpAlloc, PoolBumpAlloc},
+        bump::pool::BumpPool,
+        util::{check_send, check_sync},
+    };
+
+    use super::BumpAllocator;
+
+    #[test]
+    fn test_allocator_bounds() {
+        check_send::<BumpAllocator>();
+        check_sync::<BumpAllocator>();
+    }
+
+    #[test]
+    fn test_hash_map() {
+        let pool = BumpPool::new(1, 1024);
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let mut map = hashbrown::HashMap::new_in(alloc.allocator());
+        for idx in 0..64_u32 {
+            map.insert(idx, idx * 2);
+        }
+        let allocated = alloc.allocator();
+        let obj = alloc.alloc(1_u32);
+        drop(alloc);
+
+        // used on another thread, with another handle allocating concurrently
+        let map = std::thread::spawn(move || {
+            let mut vec = allocator_api2::vec::Vec::new_in(allocated);
+            vec.extend(0..64_u32);
+            assert_eq!(vec.len(), 64);
+            map
+        })
+        .join()
+        .unwrap();
+        assert_eq!(map.get(&10), Some(&20));
+        assert!(!obj.is_boxed());
+        drop(obj);
+        // Bump kept until the last handle dropped
+        assert_eq!(pool.len(), 0);
+        drop(map);
+        assert_eq!(pool.len(), 1);
+    }
+}
@@ -0,0 +1,308 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [`BumpBox<T>`] type, and [`unsize_bump_box!`](crate::unsize_bump_box) to convert it into trait object
+//!
+//! [`BumpFuture`](crate::future::BumpFuture) and [`BumpStream`](crate::stream::BumpStream) wrap
+//! `BumpBox<dyn Future>` and `BumpBox<dyn Stream>` converted by the same macro,
+//! [`LocalBumpBox<T>`] holds a [`LocalBumpRef`] instead for object which may be !Send
+use std::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    ptr::NonNull,
+    task::{Context, Poll},
+};
+
+use crate::{
+    bump::{BumpRef, LocalBumpRef},
+    obj::{BumpObject, UnsafeObject},
+};
+
+/// Typed owning pointer to object stored in Bump, like Box<T>
+/// It holds a BumpRef, so if it is still alive, the Bump instance it is stored will not be reset and release
+/// When object is allocated on global heap, it holds no BumpRef
+/// T may be unsized, see [`unsize_bump_box!`](crate::unsize_bump_box)
+pub struct BumpBox<T: ?Sized, R = BumpRef> {
+    ptr: NonNull<T>,
+    boxed: bool,
+    _bump_ref: Option<R>,
+    // Self owns T
+    _p: PhantomData<T>,
+}
+// Same as Box<T>, BumpRef is Send and Sync, LocalBumpRef is neither
+unsafe impl<T: ?Sized + Send, R: Send> Send for BumpBox<T, R> {}
+unsafe impl<T: ?Sized + Sync, R: Sync> Sync for BumpBox<T, R> {}
+// Same as Box<T>, object is never moved by moving the pointer
+impl<T: ?Sized, R> Unpin for BumpBox<T, R> {}
+
+/// BumpBox holds a LocalBumpRef, allocated by [`LocalBumpAlloc`](crate::alloc::LocalBumpAlloc),
+/// it is !Send and !Sync
+pub type LocalBumpBox<T> = BumpBox<T, LocalBumpRef>;
+
+impl<T> BumpBox<T> {
+    /// Allocate object on global heap, it does not hold any Bump
+    pub fn new_boxed(val: T) -> Self {
+        let ptr = NonNull::from(Box::leak(Box::new(val)));
+        unsafe { Self::from_raw(ptr, None) }
+    }
+}
+impl<T, R> BumpBox<T, R> {
+    /// # Safety
+    /// ptr must point to a valid T owned by the returned BumpBox, allocated on global heap by Box
+    /// when bump_ref is None, otherwise in the Bump which bump_ref refers to
+    pub(crate) unsafe fn from_raw(ptr: NonNull<T>, bump_ref: Option<R>) -> Self {
+        Self {
+            ptr,
+            boxed: bump_ref.is_none(),
+            _bump_ref: bump_ref,
+            _p: PhantomData,
+        }
+    }
+    /// Decompose into pointer, if it is allocated on global heap, and BumpRef,
+    /// used by [`unsize_bump_box!`](crate::unsize_bump_box)
+    #[doc(hidden)]
+    pub fn into_raw_parts(this: Self) -> (NonNull<T>, bool, Option<R>) {
+        let this = ManuallyDrop::new(this);
+        // ownership of object and BumpRef moved out
+        let bump_ref = unsafe { std::ptr::read(&this._bump_ref) };
+        (this.ptr, this.boxed, bump_ref)
+    }
+}
+impl<T: ?Sized, R> BumpBox<T, R> {
+    /// Build BumpBox of unsized type from parts returned by [`BumpBox::into_raw_parts`],
+    /// used by [`unsize_bump_box!`](crate::unsize_bump_box)
+    /// # Safety
+    /// parts must be returned by [`BumpBox::into_raw_parts`], and unsized_ptr must be
+    /// the unsizing coercion of ptr
+    #[doc(hidden)]
+    pub unsafe fn from_unsized_parts<U>(
+        ptr: NonNull<U>,
+        unsized_ptr: *mut T,
+        boxed: bool,
+        bump_ref: Option<R>,
+    ) -> Self {
+        // coercion never change the address, check in case of misuse
+        assert_eq!(
+            ptr.as_ptr().cast::<u8>(),
+            unsized_ptr.cast::<u8>(),
+            "unsized pointer must point to the same object"
+        );
+        Self {
+            ptr: NonNull::new_unchecked(unsized_ptr),
+            boxed,
+            _bump_ref: bump_ref,
+            _p: PhantomData,
+        }
+    }
+    /// Check if this object is allocated on global heap instead of Bump
+    #[inline]
+    pub fn is_boxed(&self) -> bool {
+        self.boxed
+    }
+    /// Convert into Pin<BumpBox<T>>, object stored in Bump never move until dropped, like Box::into_pin
+    pub fn into_pin(this: Self) -> Pin<Self> {
+        unsafe { Pin::new_unchecked(this) }
+    }
+}
+impl<T: ?Sized, R> Deref for BumpBox<T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+impl<T: ?Sized, R> DerefMut for BumpBox<T, R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+impl<T: ?Sized, R> Drop for BumpBox<T, R> {
+    fn drop(&mut self) {
+        // object dropped before BumpRef
+        unsafe {
+            if self.boxed {
+                drop(Box::from_raw(self.ptr.as_ptr()));
+            } else {
+                std::ptr::drop_in_place(self.ptr.as_ptr());
+            }
+        }
+    }
+}
+impl<T: ?Sized + fmt::Debug, R> fmt::Debug for BumpBox<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+// BumpBox<T> is a Future when T is Future and Unpin, like Box<T>,
+// for !Unpin T, Pin<BumpBox<T>> is a Future by the impl of Pin<P>
+impl<T: ?Sized + Future + Unpin, R> Future for BumpBox<T, R> {
+    type Output = T::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut **self).poll(cx)
+    }
+}
+impl<T: Send + 'static> From<BumpBox<T>> for BumpObject {
+    fn from(value: BumpBox<T>) -> Self {
+        let (ptr, boxed, bump_ref) = BumpBox::into_raw_parts(value);
+        let inner = unsafe { UnsafeObject::from_raw(ptr, boxed) };
+        BumpObject::from_parts(inner, bump_ref)
+    }
+}
+
+/// Convert `BumpBox<T>` into `BumpBox<dyn Trait>` on stable Rust, T must implement Trait
+/// ```
+/// use bump_future::{alloc::{BumpAlloc, HeapAlloc}, boxed::BumpBox, unsize_bump_box};
+///
+/// let val = HeapAlloc.alloc_typed(|x: u32| x + 1);
+/// let func: BumpBox<dyn Fn(u32) -> u32 + Send> = unsize_bump_box!(val, dyn Fn(u32) -> u32 + Send);
+/// assert_eq!(func(1), 2);
+/// ```
+#[macro_export]
+macro_rules! unsize_bump_box {
+    ( $boxed:expr, $t:ty ) => {{
+        let (ptr, boxed, bump_ref) = $crate::boxed::BumpBox::into_raw_parts($boxed);
+        // only unsizing coercion allowed here, no cast
+        let unsized_ptr: *mut $t = ptr.as_ptr();
+        unsafe {
+            $crate::boxed::BumpBox::<$t, _>::from_unsized_parts(ptr, unsized_ptr, boxed, bump_ref)
+        }
+    }};
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Context, Poll, Waker},
+    };
+
+    use crate::{
+        alloc::{BumpAlloc, HeapAlloc, PoolBumpAlloc},
+        bump::pool::BumpPool,
+        obj::{BumpAny, BumpObject},
+        util::{check_send, check_sync},
+    };
+
+    use super::BumpBox;
+
+    trait Handler: Send {
+        fn handle(&mut self, input: u32) -> u32;
+    }
+    struct Adder(u32, DropCounter);
+    impl Handler for Adder {
+        fn handle(&mut self, input: u32) -> u32 {
+            self.0 += input;
+            self.0
+        }
+    }
+
+    struct DropCounter(Arc<AtomicUsize>);
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_box_bounds() {
+        check_send::<BumpBox<u32>>();
+        check_sync::<BumpBox<u32>>();
+        // ensure BumpBox<T> is !Send when T is !Send, following code should not compile
+        // check_send::<BumpBox<std::rc::Rc<u32>>>();
+    }
+
+    #[test]
+    fn test_bump_box() {
+        let pool = BumpPool::new(1, 1024);
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let mut val = alloc.alloc_typed(vec![1_u32, 2]);
+        drop(alloc);
+        assert!(!val.is_boxed());
+
+        val.push(3);
+        assert_eq!(*val, vec![1, 2, 3]);
+        assert_eq!(format!("{:?}", val), "[1, 2, 3]");
+        // Bump kept until BumpBox dropped
+        assert_eq!(pool.len(), 0);
+        drop(val);
+        assert_eq!(pool.len(), 1);
+
+        let val = HeapAlloc.alloc_typed(32_u32);
+        assert!(val.is_boxed());
+        assert_eq!(*val, 32);
+    }
+
+    #[test]
+    fn test_into_object() {
+        let pool = BumpPool::new(1, 1024);
+        let drops = Arc::new(AtomicUsize::new(0));
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let val = alloc.alloc_typed(DropCounter(drops.clone()));
+        drop(alloc);
+
+        let obj: BumpObject = val.into();
+        assert!(obj.is::<DropCounter>());
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+        assert_eq!(pool.len(), 0);
+        drop(obj);
+        // dropped exactly once, and Bump recycled
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert_eq!(pool.len(), 1);
+
+        let obj: BumpObject = BumpBox::new_boxed(DropCounter(drops.clone())).into();
+        assert!(obj.is_boxed());
+        drop(obj);
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_unsize() {
+        let pool = BumpPool::new(1, 1024);
+        let drops = Arc::new(AtomicUsize::new(0));
+        let alloc = PoolBumpAlloc::new(pool.take());
+
+        let handler = alloc.alloc_typed(Adder(1, DropCounter(drops.clone())));
+        let mut handler: BumpBox<dyn Handler> = crate::unsize_bump_box!(handler, dyn Handler);
+        check_send::<BumpBox<dyn Handler>>();
+        assert_eq!(handler.handle(2), 3);
+        assert_eq!(handler.handle(3), 6);
+
+        let fut = alloc.alloc_typed(async move { 32_u32 });
+        let fut = crate::unsize_bump_box!(fut, dyn std::future::Future<Output = u32> + Send);
+        let fut = BumpBox::into_pin(fut);
+        drop(alloc);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(pin!(fut).poll(&mut cx), Poll::Ready(32));
+        assert_eq!(pool.len(), 0);
+        // dropped through vtable exactly once, and Bump recycled
+        drop(handler);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert_eq!(pool.len(), 1);
+
+        let handler = HeapAlloc.alloc_typed(Adder(1, DropCounter(drops.clone())));
+        let handler = crate::unsize_bump_box!(handler, dyn Handler);
+        assert!(handler.is_boxed());
+        drop(handler);
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+    }
+}
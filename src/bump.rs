@@ -12,66 +12,307 @@
 
 //! [Bump](https://docs.rs/bumpalo/latest/bumpalo/struct.Bump.html) instance management
 //!
-use std::{ops::Deref, sync::Weak};
+use std::{cell::Cell, ops::Deref, ptr::NonNull, rc::Rc, sync::Weak, time::Instant};
+
+// reference count protocol is model checked with loom, see `loom_recycle`
+#[cfg(bump_future_loom)]
+use loom::sync::atomic::{fence, AtomicUsize, Ordering};
+#[cfg(not(bump_future_loom))]
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
 
 use bumpalo::Bump;
-use crossbeam_queue::ArrayQueue;
-use tokio::sync::mpsc;
+use connection::ConnectionShared;
+use pool::{LimitPolicy, PoolShared};
 
+pub mod connection;
+#[cfg(feature = "metrics")]
+mod metrics;
 pub mod pool;
+mod queue;
+pub mod recycle;
 
-/// Bump usage reference manager
-pub struct BumpRefMgr {
-    rx: mpsc::Receiver<()>,
-    tx: mpsc::Sender<()>,
+/// Header of a Bump instance, pooled together with the Bump so no allocation per take.
+/// It holds the reference count shared by RecycleableBump and all BumpRef,
+/// whichever drops last resets the Bump and release it back to pool
+pub(crate) struct BumpHeader {
+    refs: AtomicUsize,
+    pub(crate) bump: Bump,
+    pool: Weak<PoolShared>,
+    // set when owned by a connection, reset and release back to it instead of pool
+    pub(crate) connection: Option<Weak<ConnectionShared>>,
+    limit_policy: LimitPolicy,
+    // when the allocator owns this Bump dropped, used to measure recycle latency
+    pub(crate) released_at: Cell<Option<Instant>>,
 }
-impl Default for BumpRefMgr {
-    fn default() -> Self {
-        Self::new()
+impl BumpHeader {
+    pub(crate) fn new(bump: Bump, pool: Weak<PoolShared>, limit_policy: LimitPolicy) -> Self {
+        Self {
+            refs: AtomicUsize::new(0),
+            bump,
+            pool,
+            connection: None,
+            limit_policy,
+            released_at: Cell::new(None),
+        }
+    }
+    /// # Safety
+    /// header must be created by `Box::into_raw`, and caller must own one reference count
+    unsafe fn release(header: NonNull<BumpHeader>) {
+        if header.as_ref().refs.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        // synchronize with all release of other references, same as Arc
+        fence(Ordering::Acquire);
+        let mut header = Box::from_raw(header.as_ptr());
+        if let Some(connection) = header.connection.as_ref().and_then(Weak::upgrade) {
+            connection.release(header);
+            return;
+        }
+        // connection dropped while this generation in flight, the Bump is owned by pool from now on
+        header.connection = None;
+        if let Some(pool) = header.pool.upgrade() {
+            pool.release(header);
+        }
     }
 }
 
-impl BumpRefMgr {
-    pub fn new() -> Self {
-        let (tx, rx) = mpsc::channel(1);
-        Self { rx, tx }
+/// Bump usage reference object
+/// any object stored in Bump must hold a BumpRef to prevent it from released
+pub struct BumpRef {
+    header: NonNull<BumpHeader>,
+}
+// BumpRef only touch the atomic reference count,
+// and the Bump is accessed only when no other reference exists
+unsafe impl Send for BumpRef {}
+unsafe impl Sync for BumpRef {}
+
+impl BumpRef {
+    /// # Safety
+    /// Bump is !Sync, caller must ensure no other thread access it at the same time
+    #[inline]
+    pub(crate) unsafe fn bump(&self) -> &Bump {
+        &self.header.as_ref().bump
     }
-    pub fn new_ref(&self) -> BumpRef {
-        BumpRef {
-            _tx: self.tx.clone(),
+    /// Policy when allocation exceed the limit of this Bump
+    #[inline]
+    pub(crate) fn limit_policy(&self) -> LimitPolicy {
+        unsafe { self.header.as_ref() }.limit_policy
+    }
+}
+impl Clone for BumpRef {
+    fn clone(&self) -> Self {
+        unsafe { self.header.as_ref() }
+            .refs
+            .fetch_add(1, Ordering::Relaxed);
+        Self {
+            header: self.header,
         }
     }
-    /// when Future resolved,it means all BumpRef dropped,
-    pub async fn wait_no_ref(mut self) {
-        drop(self.tx);
-        self.rx.recv().await;
+}
+impl Drop for BumpRef {
+    fn drop(&mut self) {
+        unsafe { BumpHeader::release(self.header) };
     }
 }
 
-/// Bump usage reference object
-/// any object stored in Bump must hold a BumpRef to prevent it from released
-pub struct BumpRef {
-    _tx: mpsc::Sender<()>,
+/// Bump usage reference object for a LocalBumpAlloc
+/// it is !Send, when the last LocalBumpRef dropped,the Bump will be reset and release back to pool
+pub struct LocalBumpRef {
+    _bump: Rc<RecycleableBump>,
+}
+impl LocalBumpRef {
+    pub(crate) fn new(bump: Rc<RecycleableBump>) -> Self {
+        Self { _bump: bump }
+    }
 }
 
-/// When dropped,Bump instance will be reset and release back to pool
+/// When dropped and no BumpRef of it alive,Bump instance will be reset and release back to pool
+/// immediately on the dropping thread
 pub struct RecycleableBump {
-    bump: Option<Bump>,
-    pool: Weak<ArrayQueue<Bump>>,
+    header: NonNull<BumpHeader>,
+}
+// RecycleableBump owns the Bump like Box<Bump>, and is !Sync because Bump is !Sync
+unsafe impl Send for RecycleableBump {}
+
+impl RecycleableBump {
+    pub(crate) fn new(header: Box<BumpHeader>) -> Self {
+        header.refs.store(1, Ordering::Relaxed);
+        Self {
+            header: NonNull::from(Box::leak(header)),
+        }
+    }
+    #[inline]
+    fn header(&self) -> &BumpHeader {
+        unsafe { self.header.as_ref() }
+    }
+    /// Create a BumpRef, the Bump will not be reset until it dropped
+    pub fn new_ref(&self) -> BumpRef {
+        self.header().refs.fetch_add(1, Ordering::Relaxed);
+        BumpRef {
+            header: self.header,
+        }
+    }
+    /// Policy when allocation exceed the limit of this Bump
+    pub fn limit_policy(&self) -> LimitPolicy {
+        self.header().limit_policy
+    }
+    /// Count an object allocated on global heap instead of this Bump, if the pool still alive
+    pub(crate) fn record_heap_fallback(&self) {
+        if let Some(pool) = self.header().pool.upgrade() {
+            pool.record_heap_fallback();
+        }
+    }
+    /// Mark the allocator owns this Bump dropped,
+    /// the Bump will be recycled after all reference dropped
+    pub(crate) fn mark_released(&self) {
+        #[cfg(feature = "metrics")]
+        self.header().released_at.set(Some(Instant::now()));
+    }
 }
 impl Deref for RecycleableBump {
     type Target = Bump;
 
     fn deref(&self) -> &Self::Target {
-        return self.bump.as_ref().expect("should not be None");
+        &self.header().bump
     }
 }
 impl Drop for RecycleableBump {
     fn drop(&mut self) {
-        let mut bump = self.bump.take().expect("should not be None");
-        bump.reset();
-        if let Some(pool) = self.pool.upgrade() {
-            let _ = pool.push(bump);
+        unsafe { BumpHeader::release(self.header) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::util::{check_send, check_sync};
+
+    use super::{pool::BumpPool, BumpRef, RecycleableBump};
+
+    #[test]
+    fn test_bump_bounds() {
+        check_send::<RecycleableBump>();
+        // ensure RecycleableBump is !Sync,following code should not compile
+        // check_sync::<RecycleableBump>();
+        check_sync::<BumpRef>();
+    }
+
+    #[test]
+    fn test_recycle_by_last_ref() {
+        let pool = BumpPool::new(1, 1024);
+        let bump = pool.take();
+        let bump_ref = bump.new_ref();
+        let bump_ref2 = bump_ref.clone();
+
+        // allocator dropped first, Bump still in use
+        drop(bump);
+        drop(bump_ref);
+        assert_eq!(pool.len(), 0);
+
+        // last reference dropped on another thread, recycled inline
+        std::thread::spawn(move || drop(bump_ref2)).join().unwrap();
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.stats().recycles, 1);
+    }
+}
+
+/// Model check the reference count protocol of RecycleableBump and BumpRef, and the pool queue
+/// pushed by the last reference and popped by take, pool counters and `Arc<PoolShared>` are std
+/// types invisible to loom, they are only read after all threads joined,
+/// run with `RUSTFLAGS="--cfg bump_future_loom" cargo test --release --lib loom_recycle`
+#[cfg(all(test, bump_future_loom))]
+mod loom_recycle {
+    use loom::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    use crate::{
+        alloc::{BumpAlloc, PoolBumpAlloc},
+        obj::BumpAny,
+    };
+
+    use super::pool::BumpPool;
+
+    #[test]
+    fn test_refcount_last_ref_recycles() {
+        loom::model(|| {
+            let pool = BumpPool::new(1, 1024);
+            let bump = pool.take();
+            let ref1 = bump.new_ref();
+            let ref2 = ref1.clone();
+            let t1 = thread::spawn(move || drop(ref1));
+            let t2 = thread::spawn(move || drop(ref2));
+            drop(bump);
+            t1.join().unwrap();
+            t2.join().unwrap();
+            // whichever dropped last recycled the Bump exactly once
+            assert_eq!(pool.len(), 1);
+            assert_eq!(pool.stats().recycles, 1);
+        });
+    }
+
+    /// set when dropped, check the Bump is not reset before
+    struct Tracked(Arc<AtomicBool>, u64);
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            assert_eq!(self.1, 32, "object dropped after Bump reset");
+            self.0.store(true, Ordering::Relaxed);
         }
     }
+
+    #[test]
+    fn test_refcount_object_dropped_before_recycle() {
+        loom::model(|| {
+            let pool = BumpPool::new(1, 1024);
+            let alloc = PoolBumpAlloc::new(pool.take());
+            let dropped = Arc::new(AtomicBool::new(false));
+            let mut obj = alloc.alloc(Tracked(dropped.clone(), 32));
+            let handle = thread::spawn(move || {
+                assert_eq!(obj.downcast_mut::<Tracked>().map(|val| val.1), Some(32));
+                drop(obj);
+            });
+            drop(alloc);
+            handle.join().unwrap();
+            assert!(dropped.load(Ordering::Relaxed));
+            assert_eq!(pool.len(), 1);
+            // Bump taken again is reset, and not shared with previous one
+            let bump = pool.take();
+            assert_eq!(pool.stats().recycles, 1);
+            drop(bump);
+        });
+    }
+
+    #[test]
+    fn test_take_while_last_ref_released() {
+        loom::model(|| {
+            let pool = BumpPool::new(1, 1024);
+            let alloc = PoolBumpAlloc::new(pool.take());
+            let dropped = Arc::new(AtomicBool::new(false));
+            let obj = alloc.alloc(Tracked(dropped.clone(), 32));
+            drop(alloc);
+            let hits = pool.stats().hits;
+
+            // last reference released into queue while another Bump taken from it
+            let handle = thread::spawn(move || drop(obj));
+            let bump = pool.take();
+            let reused = pool.stats().hits > hits;
+            if reused {
+                // popped only after the object dropped and the Bump reset
+                assert!(dropped.load(Ordering::Relaxed));
+                assert_eq!(pool.stats().recycles, 1);
+            }
+            handle.join().unwrap();
+            drop(bump);
+
+            // the queue holds one Bump, the other one dropped when it was full
+            let stats = pool.stats();
+            assert_eq!(pool.len(), 1);
+            assert_eq!(pool.in_use(), 0);
+            assert_eq!((stats.takes, stats.recycles), (2, 2));
+            assert_eq!(stats.recycle_drops, usize::from(!reused));
+        });
+    }
 }
@@ -0,0 +1,208 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [`ConnectionBump`] owns one Bump instance for a keep-alive connection
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, MutexGuard,
+};
+
+use super::{pool::PoolShared, BumpHeader, RecycleableBump};
+
+/// State shared between ConnectionBump and the Bump it owns
+pub(crate) struct ConnectionShared {
+    pool: Arc<PoolShared>,
+    slot: Mutex<Option<Box<BumpHeader>>>,
+    generation: AtomicUsize,
+}
+impl ConnectionShared {
+    #[inline]
+    fn slot(&self) -> MutexGuard<'_, Option<Box<BumpHeader>>> {
+        self.slot.lock().unwrap_or_else(|err| err.into_inner())
+    }
+    /// Reset Bump when request generation completed, and release back to connection
+    pub(crate) fn release(&self, mut header: Box<BumpHeader>) {
+        self.pool.reset(&mut header);
+        *self.slot() = Some(header);
+    }
+}
+impl Drop for ConnectionShared {
+    fn drop(&mut self) {
+        let header = self.slot.get_mut().unwrap_or_else(|err| err.into_inner());
+        if let Some(mut header) = header.take() {
+            header.connection = None;
+            self.pool.clone().release(header);
+        }
+    }
+}
+
+/// Own one Bump instance taken from pool for a connection, and hands out it to request generations,
+/// when a request generation completed, the Bump is reset and release back to the connection
+/// instead of pool, so requests of a keep-alive connection do not churn through the pool.
+/// When all clones dropped, the Bump is released back to pool.
+#[derive(Clone)]
+pub struct ConnectionBump {
+    shared: Arc<ConnectionShared>,
+}
+impl ConnectionBump {
+    pub(crate) fn new(pool: Arc<PoolShared>) -> Self {
+        let shared = Arc::new_cyclic(|this| {
+            let mut header = pool.take_header();
+            header.connection = Some(this.clone());
+            ConnectionShared {
+                pool,
+                slot: Mutex::new(Some(header)),
+                generation: AtomicUsize::new(0),
+            }
+        });
+        Self { shared }
+    }
+    /// Take the Bump of connection for a new request generation,
+    /// if previous generation still in use, e.g. pipelined or concurrent requests,
+    /// take one from pool instead
+    pub fn take(&self) -> RecycleableBump {
+        self.shared.generation.fetch_add(1, Ordering::Relaxed);
+        let header = self.shared.slot().take();
+        let header = header.unwrap_or_else(|| self.shared.pool.take_header());
+        self.shared.pool.recycleable(header)
+    }
+    /// How many request generations taken
+    pub fn generation(&self) -> usize {
+        self.shared.generation.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{convert::Infallible, future::Future};
+
+    use bytes::Bytes;
+    use http::{Request, Response};
+    use http_body_util::{BodyExt, Full};
+    use hyper::{body::Incoming, server::conn::http1, service::service_fn};
+    use hyper_util::rt::TokioIo;
+
+    use crate::{
+        bump::pool::{BumpPool, PoolConfig},
+        future::BumpFutureExt,
+        util::check_send,
+    };
+
+    use super::ConnectionBump;
+
+    crate::alloc_mod!(conn_alloc);
+
+    #[test]
+    fn test_connection_bounds() {
+        check_send::<ConnectionBump>();
+    }
+
+    #[test]
+    fn test_generation() {
+        let pool = BumpPool::new(2, 1024);
+        let conn = pool.connection();
+        assert_eq!(pool.len(), 1);
+
+        for _idx in 0..8 {
+            let bump = conn.take();
+            bump.alloc([0_u8; 256]);
+            drop(bump);
+        }
+        assert_eq!(conn.generation(), 8);
+        assert_eq!(pool.stats().takes, 1);
+        assert_eq!(pool.len(), 1);
+
+        // previous generation still in use, take from pool
+        let bump1 = conn.take();
+        let bump2 = conn.take();
+        assert_eq!(pool.len(), 0);
+        drop(bump2);
+        assert_eq!(pool.len(), 1);
+
+        // connection dropped before request completed
+        drop(conn);
+        assert_eq!(pool.len(), 1);
+        drop(bump1);
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn test_drop_connection_in_flight() {
+        let pool = BumpPool::new(1, 1024);
+        let conn = pool.connection();
+        let bump = conn.take();
+        let bump_ref = bump.new_ref();
+        drop(bump);
+
+        // generation still referenced by BumpRef when connection dropped
+        drop(conn);
+        assert_eq!(pool.len(), 0);
+        drop(bump_ref);
+        assert_eq!(pool.len(), 1);
+
+        // released to pool without the dead connection
+        let bump = pool.take();
+        assert!(bump.header().connection.is_none());
+        drop(bump);
+        assert_eq!(pool.len(), 1);
+    }
+
+    fn serve(
+        conn: &ConnectionBump,
+        _req: Request<Incoming>,
+    ) -> impl Future<Output = Result<Response<Full<Bytes>>, Infallible>> + Send + 'static {
+        conn_alloc::set_connection_bump(conn, async move {
+            let body = conn_alloc::with_task(|alloc| async move { "hello" }.bumped(alloc));
+            Ok(Response::new(Full::new(Bytes::from(body.unwrap().await))))
+        })
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive() {
+        let conf = PoolConfig::new(4, 1024);
+        let _ = conn_alloc::init(conf);
+        let pool = conn_alloc::pool();
+        let takes = pool.stats().takes;
+
+        let (client_io, server_io) = tokio::io::duplex(4096);
+        let conn = conn_alloc::connection();
+        let server_conn = conn.clone();
+        let server = tokio::spawn(async move {
+            let svc = service_fn(move |req| serve(&server_conn, req));
+            http1::Builder::new()
+                .keep_alive(true)
+                .serve_connection(TokioIo::new(server_io), svc)
+                .await
+        });
+
+        let (mut sender, client) = hyper::client::conn::http1::handshake(TokioIo::new(client_io))
+            .await
+            .unwrap();
+        let client = tokio::spawn(client);
+        for _idx in 0..32 {
+            let req = Request::new(Full::new(Bytes::new()));
+            sender.ready().await.unwrap();
+            let resp = sender.send_request(req).await.unwrap();
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(&body[..], b"hello");
+        }
+        // all requests served by the Bump of connection
+        assert_eq!(conn.generation(), 32);
+        assert_eq!(pool.stats().takes, takes + 1);
+
+        drop(sender);
+        client.await.unwrap().unwrap();
+        server.await.unwrap().unwrap();
+        drop(conn);
+        assert_eq!(pool.len(), 4);
+    }
+}
@@ -0,0 +1,261 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Emit pool metrics with [metrics](https://docs.rs/metrics/latest/metrics/) crate,
+//! every metric is labelled by the name of pool
+use std::time::Instant;
+
+use metrics::{
+    counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit,
+};
+
+pub(crate) const IN_USE: &str = "bump_future_pool_in_use";
+pub(crate) const POOL_LEN: &str = "bump_future_pool_len";
+pub(crate) const TAKES: &str = "bump_future_pool_takes_total";
+pub(crate) const MISSES: &str = "bump_future_pool_misses_total";
+pub(crate) const RECYCLES: &str = "bump_future_pool_recycles_total";
+pub(crate) const SHRINKS: &str = "bump_future_pool_shrinks_total";
+pub(crate) const TRIMMED: &str = "bump_future_pool_trimmed_total";
+pub(crate) const RECYCLE_DROPS: &str = "bump_future_pool_recycle_drops_total";
+pub(crate) const RECYCLE_LATENCY: &str = "bump_future_recycle_latency_seconds";
+pub(crate) const REQUEST_BYTES: &str = "bump_future_request_bytes";
+pub(crate) const HEAP_FALLBACKS: &str = "bump_future_pool_heap_fallbacks_total";
+
+const LABEL: &str = "pool";
+
+pub(crate) fn describe() {
+    describe_gauge!(
+        IN_USE,
+        "Bump instances taken from pool and not recycled yet"
+    );
+    describe_gauge!(POOL_LEN, "Bump instances in pool");
+    describe_counter!(TAKES, "Bump instances taken from pool");
+    describe_counter!(
+        MISSES,
+        "Takes which created a new Bump because pool is empty"
+    );
+    describe_counter!(
+        RECYCLES,
+        "Bump instances reset and released back to pool or connection"
+    );
+    describe_counter!(
+        SHRINKS,
+        "Bump instances replaced when recycle because exceed max_retained_bytes"
+    );
+    describe_counter!(TRIMMED, "Idle Bump instances released by trim");
+    describe_counter!(
+        RECYCLE_DROPS,
+        "Bump instances dropped when recycle because pool is full"
+    );
+    describe_histogram!(
+        RECYCLE_LATENCY,
+        Unit::Seconds,
+        "Time from the allocator dropped to the Bump released back to pool"
+    );
+    describe_histogram!(
+        REQUEST_BYTES,
+        Unit::Bytes,
+        "Bytes allocated by a Bump before reset"
+    );
+    describe_counter!(
+        HEAP_FALLBACKS,
+        "Objects allocated on global heap because no Bump available"
+    );
+}
+
+pub(crate) fn record_take(pool: &'static str, pool_len: usize, miss: bool) {
+    gauge!(IN_USE, LABEL => pool).increment(1.0);
+    gauge!(POOL_LEN, LABEL => pool).set(pool_len as f64);
+    counter!(TAKES, LABEL => pool).increment(1);
+    if miss {
+        counter!(MISSES, LABEL => pool).increment(1);
+    }
+}
+
+/// Bump released back to pool, or dropped because pool is full
+pub(crate) fn record_release(pool: &'static str, pool_len: usize) {
+    gauge!(IN_USE, LABEL => pool).decrement(1.0);
+    gauge!(POOL_LEN, LABEL => pool).set(pool_len as f64);
+}
+
+/// Bump reset after the last reference dropped, whether released to pool or connection
+pub(crate) fn record_recycle(
+    pool: &'static str,
+    allocated_bytes: usize,
+    released_at: Option<Instant>,
+) {
+    counter!(RECYCLES, LABEL => pool).increment(1);
+    histogram!(REQUEST_BYTES, LABEL => pool).record(allocated_bytes as f64);
+    if let Some(released_at) = released_at {
+        histogram!(RECYCLE_LATENCY, LABEL => pool).record(released_at.elapsed());
+    }
+}
+
+pub(crate) fn record_recycle_drop(pool: &'static str) {
+    counter!(RECYCLE_DROPS, LABEL => pool).increment(1);
+}
+
+pub(crate) fn record_shrink(pool: &'static str) {
+    counter!(SHRINKS, LABEL => pool).increment(1);
+}
+
+pub(crate) fn record_trim(pool: &'static str, pool_len: usize, trimmed: usize) {
+    gauge!(POOL_LEN, LABEL => pool).set(pool_len as f64);
+    counter!(TRIMMED, LABEL => pool).increment(trimmed as u64);
+}
+
+pub(crate) fn record_heap_fallback(pool: &'static str) {
+    counter!(HEAP_FALLBACKS, LABEL => pool).increment(1);
+}
+
+#[cfg(test)]
+mod test {
+    use metrics_util::{
+        debugging::{DebugValue, DebuggingRecorder},
+        CompositeKey, MetricKind,
+    };
+
+    use crate::{bump::pool::PoolConfig, future::BumpFutureExt};
+
+    use super::{
+        HEAP_FALLBACKS, IN_USE, MISSES, POOL_LEN, RECYCLES, RECYCLE_LATENCY, REQUEST_BYTES, TAKES,
+    };
+
+    // every test has its own pool, so tests running in parallel do not share Bumps and counters
+    crate::alloc_mod!(metrics_alloc);
+    crate::alloc_mod!(connection_metrics_alloc);
+
+    fn find<'a>(
+        snapshot: &'a [(
+            CompositeKey,
+            Option<metrics::Unit>,
+            Option<metrics::SharedString>,
+            DebugValue,
+        )],
+        pool: &str,
+        kind: MetricKind,
+        name: &str,
+    ) -> &'a DebugValue {
+        let (key, _, _, value) = snapshot
+            .iter()
+            .find(|(key, _, _, _)| key.kind() == kind && key.key().name() == name)
+            .unwrap_or_else(|| panic!("metric {} not emitted", name));
+        let label = key.key().labels().next().expect("should be labelled");
+        assert_eq!(label.key(), "pool");
+        assert_eq!(label.value(), pool);
+        value
+    }
+
+    #[test]
+    fn test_metrics() {
+        let conf = PoolConfig::new(1, 1024);
+        let _ = metrics_alloc::init(conf);
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap();
+            rt.block_on(async move {
+                let fut = metrics_alloc::set_bump(async move {
+                    // pool is empty now,take another Bump
+                    let fut = metrics_alloc::set_bump(async move { 1_u32 });
+                    let fut = metrics_alloc::with_task(|alloc| fut.bumped(alloc));
+                    fut.unwrap().await
+                });
+                assert_eq!(fut.await, 1);
+
+                // no Bump set, fall back to global heap
+                let buf = [1_u8; 256];
+                let fut = metrics_alloc::bumped(async move { buf[0] });
+                assert!(fut.is_boxed());
+                assert_eq!(fut.await, 1);
+            });
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let label = metrics_alloc::pool().name();
+        assert_eq!(
+            find(&snapshot, label, MetricKind::Gauge, IN_USE),
+            &DebugValue::Gauge(0.0.into())
+        );
+        assert_eq!(
+            find(&snapshot, label, MetricKind::Gauge, POOL_LEN),
+            &DebugValue::Gauge(1.0.into())
+        );
+        assert_eq!(
+            find(&snapshot, label, MetricKind::Counter, TAKES),
+            &DebugValue::Counter(2)
+        );
+        assert_eq!(
+            find(&snapshot, label, MetricKind::Counter, MISSES),
+            &DebugValue::Counter(1)
+        );
+        assert_eq!(
+            find(&snapshot, label, MetricKind::Counter, HEAP_FALLBACKS),
+            &DebugValue::Counter(1)
+        );
+        match find(&snapshot, label, MetricKind::Histogram, RECYCLE_LATENCY) {
+            DebugValue::Histogram(values) => assert_eq!(values.len(), 2),
+            _ => panic!("should be histogram"),
+        }
+        match find(&snapshot, label, MetricKind::Histogram, REQUEST_BYTES) {
+            DebugValue::Histogram(values) => {
+                assert_eq!(values.len(), 2);
+                assert!(values.iter().all(|bytes| bytes.into_inner() >= 1024.0));
+            }
+            _ => panic!("should be histogram"),
+        }
+    }
+
+    #[test]
+    fn test_connection_metrics() {
+        let conf = PoolConfig::new(1, 1024);
+        let _ = connection_metrics_alloc::init(conf);
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap();
+            rt.block_on(async move {
+                let conn = connection_metrics_alloc::connection();
+                for _idx in 0..4 {
+                    connection_metrics_alloc::set_connection_bump(&conn, async move {}).await;
+                }
+                drop(conn);
+            });
+        });
+
+        // every request generation recycled, the Bump taken from pool once
+        let snapshot = snapshotter.snapshot().into_vec();
+        let label = connection_metrics_alloc::pool().name();
+        assert_eq!(
+            find(&snapshot, label, MetricKind::Counter, TAKES),
+            &DebugValue::Counter(1)
+        );
+        assert_eq!(
+            find(&snapshot, label, MetricKind::Counter, RECYCLES),
+            &DebugValue::Counter(5)
+        );
+        assert_eq!(
+            find(&snapshot, label, MetricKind::Gauge, IN_USE),
+            &DebugValue::Gauge(0.0.into())
+        );
+        match find(&snapshot, label, MetricKind::Histogram, REQUEST_BYTES) {
+            DebugValue::Histogram(values) => assert_eq!(values.len(), 5),
+            _ => panic!("should be histogram"),
+        }
+    }
+}
@@ -10,49 +10,414 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant},
+};
 
 use bumpalo::Bump;
-use crossbeam_queue::ArrayQueue;
 
-use super::RecycleableBump;
+#[cfg(feature = "metrics")]
+use super::metrics;
+use super::{
+    connection::ConnectionBump, queue::BoundedQueue, recycle::RecycleStrategy, BumpHeader,
+    RecycleableBump,
+};
+use crate::error::PoolExhausted;
 
-/// config for BumpPool
+/// config for BumpPool, create it with [`PoolConfig::new`] or `Default`, then set fields
+/// with the `with_*` methods, it is non exhaustive so new fields can be added compatibly
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct PoolConfig {
-    /// Max instance count of pool
+    /// Max idle instance count of pool, Bump released when pool is full will be dropped,
+    /// also max instance count in use before [`BumpPool::try_take`] returns PoolExhausted
     pub pool_capacity: usize,
     /// Capacity of Bump instance
     pub bump_capacity: usize,
+    /// Max bytes a Bump instance can allocate, None means no limit
+    pub max_bump_bytes: Option<usize>,
+    /// What to do when allocation exceed `max_bump_bytes`
+    pub limit_policy: LimitPolicy,
+    /// When recycle, Bump instance allocated more than this will be dropped and
+    /// replaced with a new one of `bump_capacity`, None means always reuse
+    pub max_retained_bytes: Option<usize>,
+    /// Instance count created when pool created, and kept by [`BumpPool::trim`],
+    /// None means fill the pool to `pool_capacity`
+    pub min_idle: Option<usize>,
+    /// Bump instances beyond `min_idle` not needed during a whole `idle_timeout` will be released
+    /// by [`BumpPool::trim`], None means release immediately
+    pub idle_timeout: Option<Duration>,
+    /// How Bump instance is recycled after the last reference dropped
+    pub recycle_strategy: RecycleStrategy,
+}
+impl PoolConfig {
+    /// Config with pool and Bump capacity, other fields are default
+    pub fn new(pool_capacity: usize, bump_capacity: usize) -> Self {
+        Self {
+            pool_capacity,
+            bump_capacity,
+            ..Default::default()
+        }
+    }
+    pub fn with_max_bump_bytes(mut self, max_bump_bytes: Option<usize>) -> Self {
+        self.max_bump_bytes = max_bump_bytes;
+        self
+    }
+    pub fn with_limit_policy(mut self, limit_policy: LimitPolicy) -> Self {
+        self.limit_policy = limit_policy;
+        self
+    }
+    pub fn with_max_retained_bytes(mut self, max_retained_bytes: Option<usize>) -> Self {
+        self.max_retained_bytes = max_retained_bytes;
+        self
+    }
+    pub fn with_min_idle(mut self, min_idle: Option<usize>) -> Self {
+        self.min_idle = min_idle;
+        self
+    }
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+    pub fn with_recycle_strategy(mut self, recycle_strategy: RecycleStrategy) -> Self {
+        self.recycle_strategy = recycle_strategy;
+        self
+    }
+}
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            pool_capacity: 1024,
+            bump_capacity: 1024,
+            max_bump_bytes: None,
+            limit_policy: LimitPolicy::default(),
+            max_retained_bytes: None,
+            min_idle: None,
+            idle_timeout: None,
+            recycle_strategy: RecycleStrategy::default(),
+        }
+    }
+}
+
+/// Policy when allocation exceed the limit of Bump instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LimitPolicy {
+    /// Fallible allocation return AllocError, infallible allocation such as
+    /// [`BumpAlloc::alloc`](crate::alloc::BumpAlloc::alloc) panic
+    #[default]
+    Error,
+    /// Allocate object on global heap instead
+    Heap,
+}
+
+/// Snapshot of BumpPool statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolStats {
+    /// How many times Bump instance taken from pool
+    pub takes: usize,
+    /// How many takes got a Bump instance already in pool
+    pub hits: usize,
+    /// How many takes created a new Bump instance because pool is empty
+    pub misses: usize,
+    /// How many Bump instances reset and released back to pool or connection
+    pub recycles: usize,
+    /// How many Bump instances dropped when recycle because pool is full
+    pub recycle_drops: usize,
+    /// Total bytes currently retained by Bump instances in pool
+    pub retained_bytes: usize,
+    /// Max bytes a Bump instance allocated before reset
+    pub high_water_bytes: usize,
+    /// How many objects allocated on global heap because no Bump available,
+    /// or a request Future exceeds the limit of its Bump with [`LimitPolicy::Error`]
+    pub heap_fallbacks: usize,
+    /// How many Bump instances replaced when recycle because exceed `max_retained_bytes`
+    pub shrinks: usize,
+    /// How many idle Bump instances released by trim
+    pub trimmed: usize,
+}
+
+/// Counters of BumpPool, shared with RecycleableBump
+#[derive(Debug, Default)]
+pub(crate) struct PoolCounters {
+    takes: AtomicUsize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    recycles: AtomicUsize,
+    recycle_drops: AtomicUsize,
+    retained_bytes: AtomicUsize,
+    high_water_bytes: AtomicUsize,
+    heap_fallbacks: AtomicUsize,
+    shrinks: AtomicUsize,
+    trimmed: AtomicUsize,
+}
+impl PoolCounters {
+    fn snapshot(&self) -> PoolStats {
+        PoolStats {
+            takes: self.takes.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            recycles: self.recycles.load(Ordering::Relaxed),
+            recycle_drops: self.recycle_drops.load(Ordering::Relaxed),
+            retained_bytes: self.retained_bytes.load(Ordering::Relaxed),
+            high_water_bytes: self.high_water_bytes.load(Ordering::Relaxed),
+            heap_fallbacks: self.heap_fallbacks.load(Ordering::Relaxed),
+            shrinks: self.shrinks.load(Ordering::Relaxed),
+            trimmed: self.trimmed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// State shared between BumpPool and RecycleableBump
+pub(crate) struct PoolShared {
+    name: &'static str,
+    queue: BoundedQueue<Box<BumpHeader>>,
+    counters: PoolCounters,
+    // Bump instances taken and not released back yet
+    in_use: AtomicUsize,
+    // min queue length observed since trim window started
+    low_water: AtomicUsize,
+    trim_window: Mutex<Instant>,
+    bump_capacity: usize,
+    max_bump_bytes: Option<usize>,
+    limit_policy: LimitPolicy,
+    max_retained_bytes: Option<usize>,
+    min_idle: usize,
+    idle_timeout: Option<Duration>,
+    recycle_strategy: RecycleStrategy,
+    this: Weak<PoolShared>,
+}
+impl PoolShared {
+    fn new_header(&self) -> Box<BumpHeader> {
+        let bump = Bump::with_capacity(self.bump_capacity);
+        Box::new(BumpHeader::new(bump, self.this.clone(), self.limit_policy))
+    }
+    fn push(&self, header: Box<BumpHeader>) -> Result<(), Box<BumpHeader>> {
+        let bytes = header.bump.allocated_bytes();
+        // add before push, so retained bytes never underflow when popped concurrently
+        self.counters
+            .retained_bytes
+            .fetch_add(bytes, Ordering::Relaxed);
+        let ret = self.queue.push(header);
+        if ret.is_err() {
+            self.counters
+                .retained_bytes
+                .fetch_sub(bytes, Ordering::Relaxed);
+        }
+        ret
+    }
+    fn pop(&self) -> Option<Box<BumpHeader>> {
+        let header = self.queue.pop();
+        let len = self.queue.len();
+        if len < self.low_water.load(Ordering::Relaxed) {
+            self.low_water.fetch_min(len, Ordering::Relaxed);
+        }
+        let header = header?;
+        self.counters
+            .retained_bytes
+            .fetch_sub(header.bump.allocated_bytes(), Ordering::Relaxed);
+        Some(header)
+    }
+    /// Reset Bump and release back into the queue,
+    /// if it allocated more than `max_retained_bytes`, replace it with a new one
+    pub(crate) fn recycle(&self, mut header: Box<BumpHeader>) {
+        self.in_use.fetch_sub(1, Ordering::Relaxed);
+        self.reset(&mut header);
+        if self.push(header).is_err() {
+            self.counters.recycle_drops.fetch_add(1, Ordering::Relaxed);
+            #[cfg(feature = "metrics")]
+            metrics::record_recycle_drop(self.name);
+        }
+        #[cfg(feature = "metrics")]
+        metrics::record_release(self.name, self.queue.len());
+    }
+    /// Reset Bump, if it allocated more than `max_retained_bytes`, replace it with a new one,
+    /// counted as recycled whether it is released back to pool or to a connection
+    pub(crate) fn reset(&self, header: &mut BumpHeader) {
+        let released_at = header.released_at.take();
+        let allocated_bytes = header.bump.allocated_bytes();
+        let counters = &self.counters;
+        counters
+            .high_water_bytes
+            .fetch_max(allocated_bytes, Ordering::Relaxed);
+        match self.max_retained_bytes {
+            Some(max) if allocated_bytes > max => {
+                counters.shrinks.fetch_add(1, Ordering::Relaxed);
+                #[cfg(feature = "metrics")]
+                metrics::record_shrink(self.name);
+                header.bump = Bump::with_capacity(self.bump_capacity);
+            }
+            _ => header.bump.reset(),
+        }
+        counters.recycles.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::record_recycle(self.name, allocated_bytes, released_at);
+        #[cfg(not(feature = "metrics"))]
+        let _ = released_at;
+    }
+    /// Recycle Bump according to the recycle strategy of pool
+    pub(crate) fn release(self: Arc<Self>, header: Box<BumpHeader>) {
+        match &self.recycle_strategy {
+            RecycleStrategy::Inline => self.recycle(header),
+            RecycleStrategy::Spawn(spawner) => {
+                let spawner = spawner.clone();
+                spawner.spawn(Box::new(move || self.recycle(header)));
+            }
+        }
+    }
+    /// Pop a Bump instance from queue, or create a new one when empty
+    pub(crate) fn take_header(&self) -> Box<BumpHeader> {
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+        self.pop_or_new()
+    }
+    /// Like [`PoolShared::take_header`], but return None when `pool_capacity` instances in use
+    pub(crate) fn try_take_header(&self) -> Option<Box<BumpHeader>> {
+        let capacity = self.queue.capacity();
+        self.in_use
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |in_use| {
+                (in_use < capacity).then_some(in_use + 1)
+            })
+            .ok()?;
+        Some(self.pop_or_new())
+    }
+    fn pop_or_new(&self) -> Box<BumpHeader> {
+        let counters = &self.counters;
+        counters.takes.fetch_add(1, Ordering::Relaxed);
+        let (header, miss) = match self.pop() {
+            Some(header) => {
+                counters.hits.fetch_add(1, Ordering::Relaxed);
+                (header, false)
+            }
+            None => {
+                counters.misses.fetch_add(1, Ordering::Relaxed);
+                (self.new_header(), true)
+            }
+        };
+        #[cfg(feature = "metrics")]
+        metrics::record_take(self.name, self.queue.len(), miss);
+        #[cfg(not(feature = "metrics"))]
+        let _ = miss;
+        header
+    }
+    #[inline]
+    pub(crate) fn recycleable(&self, header: Box<BumpHeader>) -> RecycleableBump {
+        header.bump.set_allocation_limit(self.max_bump_bytes);
+        RecycleableBump::new(header)
+    }
+    pub(crate) fn record_heap_fallback(&self) {
+        self.counters.heap_fallbacks.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::record_heap_fallback(self.name);
+    }
+    /// Name of the pool
+    pub(crate) fn name(&self) -> &'static str {
+        self.name
+    }
 }
 
 /// Pool of Bump instance
 pub struct BumpPool {
-    pool: Arc<ArrayQueue<Bump>>,
-    bump_capacity: usize,
+    pool: Arc<PoolShared>,
 }
 impl BumpPool {
     pub fn new(pool_capacity: usize, bump_capacity: usize) -> Self {
-        let pool = ArrayQueue::new(pool_capacity);
-        for _idx in 0..pool_capacity {
-            let _ = pool.push(Bump::with_capacity(bump_capacity));
-        }
-        Self {
-            pool: Arc::new(pool),
-            bump_capacity,
-        }
+        Self::named("default", &PoolConfig::new(pool_capacity, bump_capacity))
+    }
+    /// Create pool with config
+    pub fn with_config(config: &PoolConfig) -> Self {
+        Self::named("default", config)
+    }
+    /// Create pool with config and a name, the name is used as label of metrics
+    pub fn named(name: &'static str, config: &PoolConfig) -> Self {
+        #[cfg(feature = "metrics")]
+        metrics::describe();
+        let min_idle = config
+            .min_idle
+            .unwrap_or(config.pool_capacity)
+            .min(config.pool_capacity);
+        let pool = Arc::new_cyclic(|this| {
+            let pool = PoolShared {
+                name,
+                queue: BoundedQueue::new(config.pool_capacity),
+                counters: PoolCounters::default(),
+                in_use: AtomicUsize::new(0),
+                low_water: AtomicUsize::new(min_idle),
+                trim_window: Mutex::new(Instant::now()),
+                bump_capacity: config.bump_capacity,
+                max_bump_bytes: config.max_bump_bytes,
+                limit_policy: config.limit_policy,
+                max_retained_bytes: config.max_retained_bytes,
+                min_idle,
+                idle_timeout: config.idle_timeout,
+                recycle_strategy: config.recycle_strategy.clone(),
+                this: this.clone(),
+            };
+            for _idx in 0..min_idle {
+                let _ = pool.push(pool.new_header());
+            }
+            pool
+        });
+        Self { pool }
+    }
+    /// Name of the pool
+    pub fn name(&self) -> &'static str {
+        self.pool.name
     }
     /// Pool cappacity
     pub fn capacity(&self) -> usize {
-        self.pool.capacity()
+        self.pool.queue.capacity()
+    }
+    /// How many Bump instance taken from pool and not released back yet
+    pub fn in_use(&self) -> usize {
+        self.pool.in_use.load(Ordering::Relaxed)
     }
     /// How many Bump instance in pool
     pub fn len(&self) -> usize {
-        self.pool.len()
+        self.pool.queue.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.pool.is_empty()
+        self.pool.queue.is_empty()
+    }
+    /// Snapshot of pool statistics
+    pub fn stats(&self) -> PoolStats {
+        self.pool.counters.snapshot()
+    }
+    /// Release idle Bump instances beyond `min_idle`, which were not needed since the last trim
+    /// at least `idle_timeout` ago, return how many released. It should be called periodically
+    /// to shrink the pool after load peak
+    pub fn trim(&self) -> usize {
+        let pool = &self.pool;
+        let mut window = pool
+            .trim_window
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let surplus = match pool.idle_timeout {
+            Some(timeout) if window.elapsed() < timeout => return 0,
+            // the pool never went below low water during the window, so that many Bumps
+            // were not needed, whichever of them, as Bumps rotate through the queue
+            Some(_) => pool.low_water.load(Ordering::Relaxed),
+            None => usize::MAX,
+        };
+        let surplus = surplus.min(pool.queue.len()).saturating_sub(pool.min_idle);
+        let mut trimmed = 0;
+        while trimmed < surplus && pool.pop().is_some() {
+            trimmed += 1;
+        }
+        // start next window
+        pool.low_water.store(pool.queue.len(), Ordering::Relaxed);
+        *window = Instant::now();
+        pool.counters.trimmed.fetch_add(trimmed, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::record_trim(pool.name, pool.queue.len(), trimmed);
+        trimmed
+    }
+    #[doc(hidden)]
+    pub fn record_heap_fallback(&self) {
+        self.pool.record_heap_fallback();
     }
 }
 impl BumpPool {
@@ -61,15 +426,163 @@ impl BumpPool {
     /// When RecycleableBump dropped, it will reset Bump and release back into the pool
     /// With the pool,we can resuse pre allocated memory in Bump instance and reduce the memory allocation syscall
     pub fn take(&self) -> RecycleableBump {
-        let pool = Arc::downgrade(&self.pool);
-        let bump = self
-            .pool
-            .pop()
-            .or_else(|| Some(Bump::with_capacity(self.bump_capacity)))
-            .unwrap();
-        RecycleableBump {
-            bump: Some(bump),
-            pool,
+        let header = self.pool.take_header();
+        self.recycleable(header)
+    }
+
+    /// Create a ConnectionBump which owns one Bump instance taken from pool for a connection
+    pub fn connection(&self) -> ConnectionBump {
+        ConnectionBump::new(self.pool.clone())
+    }
+
+    /// Take a Bump instance from pool,and return RecycleableBump
+    /// Like [`BumpPool::take`], it creates a new one when no Bump instance in pool,
+    /// but when `pool_capacity` instances already in use, it returns PoolExhausted instead,
+    /// so caller can shed load under memory pressure
+    pub fn try_take(&self) -> Result<RecycleableBump, PoolExhausted> {
+        let header = self.pool.try_take_header().ok_or(PoolExhausted)?;
+        Ok(self.recycleable(header))
+    }
+
+    #[inline]
+    fn recycleable(&self, header: Box<BumpHeader>) -> RecycleableBump {
+        self.pool.recycleable(header)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::error::PoolExhausted;
+
+    use super::{BumpPool, PoolConfig};
+
+    #[test]
+    fn test_try_take() {
+        let pool = BumpPool::new(1, 1024);
+        let bump = pool.try_take();
+        assert!(bump.is_ok());
+        assert!(pool.is_empty());
+
+        // capacity in use, should not create new one
+        assert_eq!(pool.try_take().err(), Some(PoolExhausted));
+
+        // released back to pool,can take again
+        drop(bump);
+        assert_eq!(pool.len(), 1);
+        assert!(pool.try_take().is_ok());
+    }
+
+    #[test]
+    fn test_try_take_beyond_min_idle() {
+        let conf = PoolConfig::new(4, 1024).with_min_idle(Some(1));
+        let pool = BumpPool::with_config(&conf);
+        assert_eq!(pool.len(), 1);
+
+        // created lazily up to capacity
+        let bumps = (0..4)
+            .map(|_idx| pool.try_take())
+            .collect::<Result<Vec<_>, _>>();
+        let mut bumps = bumps.expect("below capacity");
+        assert_eq!(pool.in_use(), 4);
+        assert_eq!(pool.stats().misses, 3);
+        assert_eq!(pool.try_take().err(), Some(PoolExhausted));
+
+        // Bumps taken by take count too
+        bumps.pop();
+        let bump = pool.take();
+        assert_eq!(pool.try_take().err(), Some(PoolExhausted));
+        drop(bump);
+        assert!(pool.try_take().is_ok());
+
+        drop(bumps);
+        assert_eq!(pool.in_use(), 0);
+        assert_eq!(pool.len(), 4);
+    }
+
+    #[test]
+    fn test_stats() {
+        let pool = BumpPool::new(1, 1024);
+        let stats = pool.stats();
+        assert!(stats.retained_bytes >= 1024);
+
+        let bump1 = pool.take();
+        let bump2 = pool.take();
+        bump2.alloc([0_u8; 4096]);
+        let stats = pool.stats();
+        assert_eq!(stats.takes, 2);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.retained_bytes, 0);
+
+        drop(bump2);
+        drop(bump1);
+        let stats = pool.stats();
+        assert_eq!(stats.recycles, 2);
+        // pool capacity is 1, the second Bump dropped
+        assert_eq!(stats.recycle_drops, 1);
+        assert!(stats.retained_bytes >= 1024);
+        assert!(stats.high_water_bytes >= 4096);
+    }
+
+    #[test]
+    fn test_shrink() {
+        let conf = PoolConfig::new(1, 1024).with_max_retained_bytes(Some(8192));
+        let pool = BumpPool::with_config(&conf);
+
+        // small Bump reused
+        let bump = pool.take();
+        bump.alloc([0_u8; 512]);
+        drop(bump);
+        assert_eq!(pool.stats().shrinks, 0);
+
+        // oversized Bump replaced with a new one
+        let bump = pool.take();
+        bump.alloc([0_u8; 16384]);
+        assert!(bump.allocated_bytes() > 8192);
+        drop(bump);
+        let stats = pool.stats();
+        assert_eq!(stats.shrinks, 1);
+        assert_eq!(pool.len(), 1);
+        assert!(stats.retained_bytes < 8192);
+    }
+
+    #[test]
+    fn test_elastic() {
+        let conf = PoolConfig::new(4, 1024)
+            .with_min_idle(Some(1))
+            .with_idle_timeout(Some(Duration::from_millis(50)));
+        let pool = BumpPool::with_config(&conf);
+        assert_eq!(pool.len(), 1);
+
+        // grow on demand
+        let bumps: Vec<_> = (0..4).map(|_| pool.take()).collect();
+        assert_eq!(pool.stats().misses, 3);
+        drop(bumps);
+        assert_eq!(pool.len(), 4);
+
+        // window not elapsed
+        assert_eq!(pool.trim(), 0);
+        assert_eq!(pool.len(), 4);
+
+        // all Bumps were needed during the window
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(pool.trim(), 0);
+        assert_eq!(pool.len(), 4);
+
+        // Bumps rotate through the queue, but only one needed at a time
+        for _idx in 0..8 {
+            drop(pool.take());
         }
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(pool.trim(), 2);
+        assert_eq!(pool.len(), 2);
+
+        // release idle Bumps beyond min_idle
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(pool.trim(), 1);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.stats().trimmed, 3);
     }
 }
@@ -0,0 +1,62 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bounded queue of pooled Bump instances, crossbeam ArrayQueue,
+//! or a loom Mutex under `bump_future_loom` so pushing to and popping from pool is model checked
+
+#[cfg(not(bump_future_loom))]
+pub(crate) use crossbeam_queue::ArrayQueue as BoundedQueue;
+
+#[cfg(bump_future_loom)]
+pub(crate) use self::loom_queue::BoundedQueue;
+
+#[cfg(bump_future_loom)]
+mod loom_queue {
+    use std::collections::VecDeque;
+
+    use loom::sync::Mutex;
+
+    /// Same methods as crossbeam ArrayQueue used by pool
+    pub(crate) struct BoundedQueue<T> {
+        inner: Mutex<VecDeque<T>>,
+        capacity: usize,
+    }
+    impl<T> BoundedQueue<T> {
+        pub(crate) fn new(capacity: usize) -> Self {
+            assert!(capacity > 0, "capacity must be non-zero");
+            Self {
+                inner: Mutex::new(VecDeque::with_capacity(capacity)),
+                capacity,
+            }
+        }
+        pub(crate) fn push(&self, val: T) -> Result<(), T> {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.len() == self.capacity {
+                return Err(val);
+            }
+            inner.push_back(val);
+            Ok(())
+        }
+        pub(crate) fn pop(&self) -> Option<T> {
+            self.inner.lock().unwrap().pop_front()
+        }
+        pub(crate) fn len(&self) -> usize {
+            self.inner.lock().unwrap().len()
+        }
+        pub(crate) fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+        pub(crate) fn capacity(&self) -> usize {
+            self.capacity
+        }
+    }
+}
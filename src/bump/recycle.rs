@@ -0,0 +1,149 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How a Bump instance is reset and released back to pool after the last reference dropped
+use std::{fmt, sync::Arc};
+
+/// Recycle work of a Bump instance, reset it and release back to pool
+pub type RecycleTask = Box<dyn FnOnce() + Send + 'static>;
+
+/// Executor abstraction used to run recycle work off the dropping thread
+/// implement it for any executor, or use a closure
+/// ```
+/// use std::sync::Arc;
+/// use bump_future::bump::recycle::{RecycleStrategy, RecycleTask};
+///
+/// let strategy = RecycleStrategy::Spawn(Arc::new(|task: RecycleTask| {
+///     std::thread::spawn(task);
+/// }));
+/// ```
+pub trait Spawner: Send + Sync + 'static {
+    /// Run the task later, it must be run exactly once otherwise the Bump is leaked
+    fn spawn(&self, task: RecycleTask);
+}
+impl<F> Spawner for F
+where
+    F: Fn(RecycleTask) + Send + Sync + 'static,
+{
+    fn spawn(&self, task: RecycleTask) {
+        self(task)
+    }
+}
+
+/// Strategy to recycle Bump instance when the last reference dropped
+#[derive(Clone, Default)]
+pub enum RecycleStrategy {
+    /// Reset and release back to pool on the dropping thread, no executor required
+    #[default]
+    Inline,
+    /// Hand over the recycle work to a Spawner,
+    /// useful when Bump instances are large and reset is expensive
+    Spawn(Arc<dyn Spawner>),
+}
+impl fmt::Debug for RecycleStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inline => write!(f, "Inline"),
+            Self::Spawn(_) => write!(f, "Spawn(..)"),
+        }
+    }
+}
+
+/// Spawner run recycle work as a tokio task
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone)]
+pub struct TokioSpawner {
+    handle: tokio::runtime::Handle,
+}
+#[cfg(feature = "tokio")]
+impl TokioSpawner {
+    pub fn new(handle: tokio::runtime::Handle) -> Self {
+        Self { handle }
+    }
+    /// Spawner of the current tokio runtime
+    /// must call within tokio runtime otherwise will panic
+    pub fn current() -> Self {
+        Self::new(tokio::runtime::Handle::current())
+    }
+}
+#[cfg(feature = "tokio")]
+impl Spawner for TokioSpawner {
+    fn spawn(&self, task: RecycleTask) {
+        self.handle.spawn(async move { task() });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll, Waker},
+    };
+
+    use crate::{
+        alloc::PoolBumpAlloc,
+        bump::pool::{BumpPool, PoolConfig},
+        future::BumpFutureExt,
+    };
+
+    use super::{RecycleStrategy, RecycleTask};
+
+    #[test]
+    fn test_no_executor() {
+        let pool = BumpPool::new(1, 1024);
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let fut = async move { 32_u32 }.bumped(&alloc);
+        drop(alloc);
+        assert_eq!(pool.len(), 0);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(pin!(fut).poll(&mut cx), Poll::Ready(32));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_spawn_strategy() {
+        let tasks: Arc<Mutex<Vec<RecycleTask>>> = Arc::default();
+        let queued = tasks.clone();
+        let conf = PoolConfig::new(1, 1024).with_recycle_strategy(RecycleStrategy::Spawn(
+            Arc::new(move |task: RecycleTask| {
+                queued.lock().unwrap().push(task);
+            }),
+        ));
+        let pool = BumpPool::with_config(&conf);
+
+        drop(pool.take());
+        // recycle work handed over to spawner, not run yet
+        assert_eq!(pool.len(), 0);
+        assert_eq!(tasks.lock().unwrap().len(), 1);
+
+        let task = tasks.lock().unwrap().pop().unwrap();
+        task();
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.stats().recycles, 1);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_tokio_spawner() {
+        let conf = PoolConfig::new(1, 1024).with_recycle_strategy(RecycleStrategy::Spawn(
+            Arc::new(super::TokioSpawner::current()),
+        ));
+        let pool = BumpPool::with_config(&conf);
+        drop(pool.take());
+        assert_eq!(pool.len(), 0);
+        tokio::task::yield_now().await;
+        assert_eq!(pool.len(), 1);
+    }
+}
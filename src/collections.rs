@@ -0,0 +1,631 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Owning collections stored in Bump: [`BumpVec<T>`], [`BumpString`] and [`BumpBytes`]
+//!
+//! Unlike collections of bumpalo, they do not borrow the Bump but hold a BumpRef,
+//! so they are `'static + Send` and can be moved into BumpFuture and across `.await`.
+//! Bump is !Sync and may be used by the allocator on another thread, so they can not allocate
+//! by the BumpRef they hold, growth in Bump needs the allocator, use the `*_in` methods e.g.
+//! [`BumpVec::push_in`], growth by the other methods moves elements onto global heap.
+use std::{
+    alloc::Layout,
+    fmt,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    str,
+};
+
+use crate::{alloc::BumpAlloc, bump::BumpRef};
+
+enum RawVec<T> {
+    Bump {
+        ptr: NonNull<T>,
+        len: usize,
+        cap: usize,
+        _bump_ref: BumpRef,
+    },
+    Heap(Vec<T>),
+}
+
+/// Vec stored in Bump, it holds a BumpRef so the Bump will not be reset and release until it dropped
+pub struct BumpVec<T: 'static> {
+    raw: RawVec<T>,
+    // Self owns T
+    _p: PhantomData<T>,
+}
+// Same as Vec<T>, BumpRef is Send and Sync
+unsafe impl<T: Send + 'static> Send for BumpVec<T> {}
+unsafe impl<T: Sync + 'static> Sync for BumpVec<T> {}
+
+impl<T: 'static> BumpVec<T> {
+    /// Create an empty BumpVec without allocation, it grows in Bump by [`BumpVec::push_in`],
+    /// or on global heap by [`BumpVec::push`]
+    pub fn new() -> Self {
+        Self::from_vec(Vec::new())
+    }
+    /// Create BumpVec with capacity allocated in the Bump of alloc,
+    /// or on global heap when alloc can not allocate in Bump
+    pub fn with_capacity_in<A>(capacity: usize, alloc: &A) -> Self
+    where
+        A: BumpAlloc,
+    {
+        if capacity == 0 || mem::size_of::<T>() == 0 {
+            return Self::from_vec(Vec::with_capacity(capacity));
+        }
+        let layout = Layout::array::<T>(capacity).expect("capacity overflow");
+        match alloc.alloc_raw(layout) {
+            Some((ptr, bump_ref)) => Self {
+                raw: RawVec::Bump {
+                    ptr: ptr.cast(),
+                    len: 0,
+                    cap: capacity,
+                    _bump_ref: bump_ref,
+                },
+                _p: PhantomData,
+            },
+            None => Self::from_vec(Vec::with_capacity(capacity)),
+        }
+    }
+    /// Create BumpVec by clone elements of slice into the Bump of alloc
+    pub fn from_slice_in<A>(slice: &[T], alloc: &A) -> Self
+    where
+        A: BumpAlloc,
+        T: Clone,
+    {
+        let mut vec = Self::with_capacity_in(slice.len(), alloc);
+        vec.extend_from_slice(slice);
+        vec
+    }
+    fn from_vec(vec: Vec<T>) -> Self {
+        Self {
+            raw: RawVec::Heap(vec),
+            _p: PhantomData,
+        }
+    }
+    /// Check if elements are stored on global heap instead of Bump
+    #[inline]
+    pub fn is_boxed(&self) -> bool {
+        matches!(self.raw, RawVec::Heap(_))
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        match &self.raw {
+            RawVec::Bump { len, .. } => *len,
+            RawVec::Heap(vec) => vec.len(),
+        }
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        match &self.raw {
+            RawVec::Bump { cap, .. } => *cap,
+            RawVec::Heap(vec) => vec.capacity(),
+        }
+    }
+    pub fn as_slice(&self) -> &[T] {
+        match &self.raw {
+            RawVec::Bump { ptr, len, .. } => unsafe {
+                std::slice::from_raw_parts(ptr.as_ptr(), *len)
+            },
+            RawVec::Heap(vec) => vec.as_slice(),
+        }
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        match &mut self.raw {
+            RawVec::Bump { ptr, len, .. } => unsafe {
+                std::slice::from_raw_parts_mut(ptr.as_ptr(), *len)
+            },
+            RawVec::Heap(vec) => vec.as_mut_slice(),
+        }
+    }
+    /// Reserve capacity for at least additional more elements, when capacity is not enough,
+    /// elements are moved into a larger space in the Bump of alloc, or on global heap
+    /// when alloc can not allocate in Bump
+    pub fn reserve_in<A>(&mut self, additional: usize, alloc: &A)
+    where
+        A: BumpAlloc,
+    {
+        let len = self.len();
+        let required = len.checked_add(additional).expect("capacity overflow");
+        if required <= self.capacity() {
+            return;
+        }
+        let mut grown =
+            Self::with_capacity_in(required.max(self.capacity().saturating_mul(2)), alloc);
+        unsafe {
+            ptr::copy_nonoverlapping(self.as_ptr(), grown.as_mut_ptr(), len);
+            // elements moved, release the old space without drop them
+            self.set_len(0);
+            grown.set_len(len);
+        }
+        *self = grown;
+    }
+    /// Like [`BumpVec::push`], but grow in the Bump of alloc when capacity is not enough
+    pub fn push_in<A>(&mut self, val: T, alloc: &A)
+    where
+        A: BumpAlloc,
+    {
+        self.reserve_in(1, alloc);
+        self.push(val);
+    }
+    /// Reserve capacity for at least additional more elements,
+    /// when capacity in Bump is not enough, elements are moved onto global heap,
+    /// use [`BumpVec::reserve_in`] to grow in Bump
+    pub fn reserve(&mut self, additional: usize) {
+        match &mut self.raw {
+            RawVec::Bump { ptr, len, cap, .. } => {
+                let required = len.checked_add(additional).expect("capacity overflow");
+                if required <= *cap {
+                    return;
+                }
+                let mut vec = Vec::with_capacity(required.max(cap.saturating_mul(2)));
+                unsafe {
+                    ptr::copy_nonoverlapping(ptr.as_ptr(), vec.as_mut_ptr(), *len);
+                    vec.set_len(*len);
+                }
+                // elements moved out, release the BumpRef
+                *len = 0;
+                self.raw = RawVec::Heap(vec);
+            }
+            RawVec::Heap(vec) => vec.reserve(additional),
+        }
+    }
+    pub fn push(&mut self, val: T) {
+        self.reserve(1);
+        match &mut self.raw {
+            RawVec::Bump { ptr, len, .. } => {
+                unsafe { ptr.as_ptr().add(*len).write(val) };
+                *len += 1;
+            }
+            RawVec::Heap(vec) => vec.push(val),
+        }
+    }
+    pub fn pop(&mut self) -> Option<T> {
+        match &mut self.raw {
+            RawVec::Bump { ptr, len, .. } => {
+                if *len == 0 {
+                    return None;
+                }
+                *len -= 1;
+                Some(unsafe { ptr.as_ptr().add(*len).read() })
+            }
+            RawVec::Heap(vec) => vec.pop(),
+        }
+    }
+    /// Shorten the vector to len, drop the rest elements
+    pub fn truncate(&mut self, new_len: usize) {
+        match &mut self.raw {
+            RawVec::Bump { ptr, len, .. } => {
+                if new_len >= *len {
+                    return;
+                }
+                let tail = ptr::slice_from_raw_parts_mut(
+                    unsafe { ptr.as_ptr().add(new_len) },
+                    *len - new_len,
+                );
+                // set len first in case drop panic
+                *len = new_len;
+                unsafe { ptr::drop_in_place(tail) };
+            }
+            RawVec::Heap(vec) => vec.truncate(new_len),
+        }
+    }
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+    /// Clone and append all elements of other, capacity reserved once
+    pub fn extend_from_slice(&mut self, other: &[T])
+    where
+        T: Clone,
+    {
+        self.reserve(other.len());
+        match &mut self.raw {
+            RawVec::Bump { ptr, len, .. } => {
+                for val in other {
+                    unsafe { ptr.as_ptr().add(*len).write(val.clone()) };
+                    // count every element written in case clone panic
+                    *len += 1;
+                }
+            }
+            RawVec::Heap(vec) => vec.extend_from_slice(other),
+        }
+    }
+    /// Like [`BumpVec::extend_from_slice`], but copy all elements at once
+    pub fn extend_from_slice_copy(&mut self, other: &[T])
+    where
+        T: Copy,
+    {
+        self.reserve(other.len());
+        let len = self.len();
+        unsafe {
+            ptr::copy_nonoverlapping(other.as_ptr(), self.as_mut_ptr().add(len), other.len());
+            self.set_len(len + other.len());
+        }
+    }
+    #[inline]
+    fn as_ptr(&self) -> *const T {
+        match &self.raw {
+            RawVec::Bump { ptr, .. } => ptr.as_ptr(),
+            RawVec::Heap(vec) => vec.as_ptr(),
+        }
+    }
+    #[inline]
+    fn as_mut_ptr(&mut self) -> *mut T {
+        match &mut self.raw {
+            RawVec::Bump { ptr, .. } => ptr.as_ptr(),
+            RawVec::Heap(vec) => vec.as_mut_ptr(),
+        }
+    }
+    /// # Safety
+    /// same as Vec::set_len
+    #[inline]
+    unsafe fn set_len(&mut self, new_len: usize) {
+        match &mut self.raw {
+            RawVec::Bump { len, .. } => *len = new_len,
+            RawVec::Heap(vec) => vec.set_len(new_len),
+        }
+    }
+}
+impl<T: 'static> Default for BumpVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T: 'static> Drop for BumpVec<T> {
+    fn drop(&mut self) {
+        // elements dropped before BumpRef
+        if let RawVec::Bump { .. } = self.raw {
+            self.clear();
+        }
+    }
+}
+impl<T: 'static> Deref for BumpVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        self.as_slice()
+    }
+}
+impl<T: 'static> DerefMut for BumpVec<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.as_mut_slice()
+    }
+}
+impl<T: 'static> AsRef<[T]> for BumpVec<T> {
+    fn as_ref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+impl<T: 'static> Extend<T> for BumpVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for val in iter {
+            self.push(val);
+        }
+    }
+}
+impl<T: fmt::Debug + 'static> fmt::Debug for BumpVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+/// String stored in Bump, it holds a BumpRef so the Bump will not be reset and release until it dropped
+#[derive(Default)]
+pub struct BumpString {
+    vec: BumpVec<u8>,
+}
+impl BumpString {
+    /// Create an empty BumpString without allocation, it grows in Bump by [`BumpString::push_str_in`],
+    /// or on global heap by [`BumpString::push_str`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Create BumpString with capacity in bytes allocated in the Bump of alloc
+    pub fn with_capacity_in<A>(capacity: usize, alloc: &A) -> Self
+    where
+        A: BumpAlloc,
+    {
+        Self {
+            vec: BumpVec::with_capacity_in(capacity, alloc),
+        }
+    }
+    /// Create BumpString by copy s into the Bump of alloc
+    pub fn from_str_in<A>(s: &str, alloc: &A) -> Self
+    where
+        A: BumpAlloc,
+    {
+        let mut string = Self::new();
+        string.push_str_in(s, alloc);
+        string
+    }
+    /// Check if content is stored on global heap instead of Bump
+    #[inline]
+    pub fn is_boxed(&self) -> bool {
+        self.vec.is_boxed()
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.vec.capacity()
+    }
+    pub fn as_str(&self) -> &str {
+        // only valid utf8 pushed
+        unsafe { str::from_utf8_unchecked(self.vec.as_slice()) }
+    }
+    /// Append s, when capacity is not enough, content is moved onto global heap
+    pub fn push_str(&mut self, s: &str) {
+        self.vec.extend_from_slice_copy(s.as_bytes());
+    }
+    /// Like [`BumpString::push_str`], but grow in the Bump of alloc when capacity is not enough
+    pub fn push_str_in<A>(&mut self, s: &str, alloc: &A)
+    where
+        A: BumpAlloc,
+    {
+        self.vec.reserve_in(s.len(), alloc);
+        self.vec.extend_from_slice_copy(s.as_bytes());
+    }
+    /// Reserve capacity for at least additional more bytes in the Bump of alloc
+    pub fn reserve_in<A>(&mut self, additional: usize, alloc: &A)
+    where
+        A: BumpAlloc,
+    {
+        self.vec.reserve_in(additional, alloc);
+    }
+    pub fn push(&mut self, ch: char) {
+        self.push_str(ch.encode_utf8(&mut [0_u8; 4]));
+    }
+    pub fn clear(&mut self) {
+        self.vec.clear();
+    }
+    /// Convert into immutable BumpBytes without copy
+    pub fn into_bytes(self) -> BumpBytes {
+        BumpBytes::from(self.vec)
+    }
+}
+impl Deref for BumpString {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+impl AsRef<str> for BumpString {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+impl AsRef<[u8]> for BumpString {
+    fn as_ref(&self) -> &[u8] {
+        self.vec.as_slice()
+    }
+}
+impl fmt::Write for BumpString {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+impl fmt::Display for BumpString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+impl fmt::Debug for BumpString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Immutable bytes stored in Bump, it holds a BumpRef so the Bump will not be reset and release until it dropped
+/// With `bytes` feature, it can be converted into [`bytes::Bytes`] without copy
+pub struct BumpBytes {
+    vec: BumpVec<u8>,
+}
+impl BumpBytes {
+    /// Create BumpBytes by copy data into the Bump of alloc
+    pub fn copy_from_slice_in<A>(data: &[u8], alloc: &A) -> Self
+    where
+        A: BumpAlloc,
+    {
+        let mut vec = BumpVec::with_capacity_in(data.len(), alloc);
+        vec.extend_from_slice_copy(data);
+        Self::from(vec)
+    }
+    /// Check if content is stored on global heap instead of Bump
+    #[inline]
+    pub fn is_boxed(&self) -> bool {
+        self.vec.is_boxed()
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        self.vec.as_slice()
+    }
+}
+impl From<BumpVec<u8>> for BumpBytes {
+    fn from(vec: BumpVec<u8>) -> Self {
+        Self { vec }
+    }
+}
+impl From<BumpString> for BumpBytes {
+    fn from(string: BumpString) -> Self {
+        string.into_bytes()
+    }
+}
+impl Deref for BumpBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.as_slice()
+    }
+}
+impl AsRef<[u8]> for BumpBytes {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+impl fmt::Debug for BumpBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+#[cfg(feature = "bytes")]
+impl From<BumpBytes> for bytes::Bytes {
+    /// Bytes takes the ownership of BumpBytes, the Bump will not be reset until all Bytes dropped
+    fn from(mut value: BumpBytes) -> Self {
+        if let RawVec::Heap(vec) = &mut value.vec.raw {
+            return bytes::Bytes::from(mem::take(vec));
+        }
+        bytes::Bytes::from_owner(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::Write;
+
+    use crate::{
+        alloc::{HeapAlloc, PoolBumpAlloc},
+        bump::pool::BumpPool,
+        future::BumpFutureExt,
+        util::{check_send, check_sync},
+    };
+
+    use super::{BumpBytes, BumpString, BumpVec};
+
+    #[test]
+    fn test_collections_bounds() {
+        check_send::<BumpVec<u32>>();
+        check_sync::<BumpVec<u32>>();
+        check_send::<BumpString>();
+        check_send::<BumpBytes>();
+        // ensure BumpVec<T> is !Send when T is !Send, following code should not compile
+        // check_send::<BumpVec<std::rc::Rc<u32>>>();
+    }
+
+    #[test]
+    fn test_bump_vec() {
+        let pool = BumpPool::new(1, 1024);
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let mut vec = BumpVec::with_capacity_in(2, &alloc);
+        drop(alloc);
+        vec.push(String::from("a"));
+        vec.push(String::from("b"));
+        assert!(!vec.is_boxed());
+        assert_eq!(vec.pop().as_deref(), Some("b"));
+        vec.extend([String::from("c"), String::from("d")]);
+        // grow beyond capacity, moved onto global heap and Bump released
+        assert!(vec.is_boxed());
+        assert_eq!(pool.len(), 1);
+        assert_eq!(vec.as_slice(), ["a", "c", "d"]);
+
+        let vec = BumpVec::from_slice_in(&[1_u32, 2], &HeapAlloc);
+        assert!(vec.is_boxed());
+        assert_eq!(*vec, [1, 2]);
+    }
+
+    #[test]
+    fn test_grow_in_bump() {
+        let pool = BumpPool::new(1, 1024);
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let mut vec = BumpVec::new();
+        for idx in 0..16_u32 {
+            vec.push_in(idx.to_string(), &alloc);
+        }
+        // grown in Bump, elements moved exactly once
+        assert!(!vec.is_boxed());
+        assert_eq!(vec.len(), 16);
+        assert_eq!(vec[15], "15");
+
+        let mut string = BumpString::new();
+        for _idx in 0..8 {
+            string.push_str_in("hello", &alloc);
+        }
+        assert!(!string.is_boxed());
+        assert_eq!(string.len(), 40);
+        drop(alloc);
+        drop(vec);
+        assert_eq!(pool.len(), 0);
+        drop(string);
+        assert_eq!(pool.len(), 1);
+
+        let mut vec = BumpVec::new();
+        vec.push_in(1_u32, &HeapAlloc);
+        assert!(vec.is_boxed());
+    }
+
+    #[tokio::test]
+    async fn test_across_await() {
+        let pool = BumpPool::new(1, 1024);
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let mut string = BumpString::with_capacity_in(64, &alloc);
+        write!(string, "hello {}", 32).unwrap();
+        let fut = async move {
+            tokio::task::yield_now().await;
+            string.push('!');
+            string
+        }
+        .bumped(&alloc);
+        drop(alloc);
+
+        let string = tokio::spawn(fut).await.unwrap();
+        assert!(!string.is_boxed());
+        assert_eq!(string.as_str(), "hello 32!");
+        assert_eq!(pool.len(), 0);
+
+        let bytes = string.into_bytes();
+        assert_eq!(&*bytes, b"hello 32!");
+        drop(bytes);
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_into_bytes() {
+        let pool = BumpPool::new(1, 1024);
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let bytes = BumpBytes::copy_from_slice_in(b"hello", &alloc);
+        drop(alloc);
+
+        let bytes = bytes::Bytes::from(bytes);
+        let part = bytes.slice(1..3);
+        drop(bytes);
+        assert_eq!(&part[..], b"el");
+        assert_eq!(pool.len(), 0);
+        drop(part);
+        assert_eq!(pool.len(), 1);
+
+        let bytes = bytes::Bytes::from(BumpBytes::copy_from_slice_in(b"hello", &HeapAlloc));
+        assert_eq!(&bytes[..], b"hello");
+    }
+}
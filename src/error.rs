@@ -0,0 +1,81 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Error types for fallible allocation
+use std::{alloc::Layout, error::Error, fmt};
+
+/// Returned when allocate object in Bump failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError {
+    layout: Layout,
+    kind: AllocErrorKind,
+}
+impl AllocError {
+    pub(crate) fn new(layout: Layout, kind: AllocErrorKind) -> Self {
+        Self { layout, kind }
+    }
+    /// Layout of the object failed to allocate
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+    /// Why the allocation failed
+    pub fn kind(&self) -> AllocErrorKind {
+        self.kind
+    }
+}
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.kind {
+            AllocErrorKind::OutOfMemory => "out of memory",
+            AllocErrorKind::LimitExceeded => "allocation limit exceeded",
+        };
+        write!(
+            f,
+            "failed to allocate {} bytes (align {}) in Bump: {}",
+            self.layout.size(),
+            self.layout.align(),
+            reason
+        )
+    }
+}
+impl Error for AllocError {}
+
+/// Kind of [`AllocError`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocErrorKind {
+    /// Global allocator failed to allocate new chunk for Bump
+    OutOfMemory,
+    /// Bump has an allocation limit, and new chunk would exceed it
+    LimitExceeded,
+}
+
+/// Returned when no Bump instance left in pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolExhausted;
+
+impl fmt::Display for PoolExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no Bump instance left in pool")
+    }
+}
+impl Error for PoolExhausted {}
+
+/// Returned when no Bump scope set for the current Future, e.g. `BumpLayer` is not applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingBump;
+
+impl fmt::Display for MissingBump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no Bump set for the current Future")
+    }
+}
+impl Error for MissingBump {}
@@ -0,0 +1,167 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! axum integration, use [`BumpLayer`](crate::service::BumpLayer) as middleware to scope every request
+//! in its own Bump, and [`RequestBump`] extractor to allocate in it from handlers
+//! ```
+//! use axum::{routing::get, Router};
+//! use bump_future::{alloc_mod, extract::RequestBump, service::BumpLayer};
+//!
+//! alloc_mod!(bump_alloc);
+//!
+//! async fn handler(bump: RequestBump<bump_alloc::Scope>) -> String {
+//!     bump.bumped(async move { String::from("hello") }).await
+//! }
+//!
+//! let app: Router = Router::new()
+//!     .route("/", get(handler))
+//!     .layer(BumpLayer::<bump_alloc::Scope>::new());
+//! ```
+use std::{fmt, future::Future, marker::PhantomData};
+
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    alloc::{BumpAlloc, HeapAlloc, PoolBumpAlloc},
+    boxed::BumpBox,
+    error::MissingBump,
+    future::{BumpFuture, BumpFutureExt},
+    scope::{with_scope, BumpScope},
+};
+
+/// Extractor gives handler access to the PoolBumpAlloc of request scope S,
+/// extraction is rejected with [`MissingBump`] if the request is not inside the scope
+pub struct RequestBump<S> {
+    _p: PhantomData<fn() -> S>,
+}
+impl<S> RequestBump<S>
+where
+    S: BumpScope,
+{
+    /// Access the PoolBumpAlloc of request, return None when called outside the request Future
+    pub fn with<F, R>(&self, func: F) -> Option<R>
+    where
+        F: FnOnce(&PoolBumpAlloc) -> R,
+    {
+        with_scope(S::key(), func)
+    }
+    /// Convert the Future input into BumpFuture stored in the Bump of request,
+    /// or on global heap when called outside the request Future
+    pub fn bumped<F>(&self, fut: F) -> BumpFuture<F::Output>
+    where
+        F: Future + Send + 'static,
+    {
+        let mut fut = Some(fut);
+        self.with(|alloc| fut.take().unwrap().bumped(alloc))
+            .unwrap_or_else(|| fut.take().unwrap().bumped(&HeapAlloc))
+    }
+    /// Allocate a typed BumpBox in the Bump of request,
+    /// or on global heap when called outside the request Future
+    pub fn alloc_typed<T>(&self, val: T) -> BumpBox<T>
+    where
+        T: Send + 'static,
+    {
+        let mut val = Some(val);
+        self.with(|alloc| alloc.alloc_typed(val.take().unwrap()))
+            .unwrap_or_else(|| HeapAlloc.alloc_typed(val.take().unwrap()))
+    }
+}
+impl<S> Clone for RequestBump<S> {
+    fn clone(&self) -> Self {
+        Self { _p: PhantomData }
+    }
+}
+impl<S> fmt::Debug for RequestBump<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestBump").finish()
+    }
+}
+impl<S, St> FromRequestParts<St> for RequestBump<S>
+where
+    S: BumpScope,
+    St: Send + Sync,
+{
+    type Rejection = MissingBump;
+
+    async fn from_request_parts(_parts: &mut Parts, _state: &St) -> Result<Self, Self::Rejection> {
+        let this = Self { _p: PhantomData };
+        this.with(|_alloc| ()).ok_or(MissingBump)?;
+        Ok(this)
+    }
+}
+
+impl IntoResponse for MissingBump {
+    fn into_response(self) -> Response {
+        (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::{bump::pool::PoolConfig, service::BumpLayer};
+
+    use super::RequestBump;
+
+    crate::alloc_mod!(axum_alloc);
+
+    async fn handler(bump: RequestBump<axum_alloc::Scope>) -> String {
+        let name = bump.alloc_typed(String::from("axum"));
+        let fut = bump.bumped(async move { format!("hello {}", *name) });
+        assert!(!fut.is_boxed());
+        fut.await
+    }
+
+    async fn get_body(app: Router, uri: &str) -> (StatusCode, String) {
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        let status = resp.status();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_request_bump() {
+        let conf = PoolConfig::new(2, 1024);
+        let _ = axum_alloc::init(conf);
+        let pool = axum_alloc::pool();
+
+        let app = Router::new()
+            .route("/", get(handler))
+            .layer(BumpLayer::<axum_alloc::Scope>::new());
+        let takes = pool.stats().takes;
+        for _idx in 0..4 {
+            let rslt = get_body(app.clone(), "/").await;
+            assert_eq!(rslt, (StatusCode::OK, String::from("hello axum")));
+        }
+        // every request take a Bump and release back after response
+        assert_eq!(pool.stats().takes, takes + 4);
+        assert_eq!(pool.len(), 2);
+
+        // rejected without middleware
+        let app = Router::new().route("/", get(handler));
+        let (status, _body) = get_body(app, "/").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -10,54 +10,53 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! [`BumpFuture<O>`] type
+//! [`BumpFuture<O>`] type, and [`LocalBumpFuture<O>`] for !Send Future
 //!
 use std::{
     future::Future,
-    marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
 };
 
-use crate::{alloc::BumpAlloc, obj::BumpObject, util::poll_future};
+use crate::{
+    alloc::{BumpAlloc, LocalBumpAlloc},
+    boxed::{BumpBox, LocalBumpBox},
+    error::AllocError,
+};
 
 /// Type erased Future,stored in Bump
+/// It wraps a `BumpBox<dyn Future + Send>`, polled through the vtable
 pub struct BumpFuture<O> {
-    inner: BumpObject,
-    poll_fn: fn(this: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<O>,
-    // Self is essentially a pointer,so is Unpin
-    _p: PhantomData<dyn Future<Output = O> + Send + Unpin + 'static>,
+    // pinned once wrapped, never moved out, BumpBox is a pointer, so Self is Unpin
+    inner: BumpBox<dyn Future<Output = O> + Send>,
 }
 impl<O> BumpFuture<O> {
-    pub(crate) fn new(
-        inner: BumpObject,
-        poll_fn: fn(this: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<O>,
-    ) -> Self {
-        Self {
-            inner,
-            poll_fn,
-            _p: PhantomData,
-        }
+    /// Unsize BumpBox of Future F, the Future is pinned from now on
+    pub(crate) fn from_box<F>(fut: BumpBox<F>) -> Self
+    where
+        F: Future<Output = O> + Send + 'static,
+    {
+        let fut = crate::unsize_bump_box!(fut, dyn Future<Output = O> + Send);
+        Self::from(fut)
     }
-}
-impl<O> AsRef<BumpObject> for BumpFuture<O> {
-    fn as_ref(&self) -> &BumpObject {
-        &self.inner
+    /// Check if the Future is allocated on global heap
+    #[inline]
+    pub fn is_boxed(&self) -> bool {
+        self.inner.is_boxed()
     }
 }
-impl<O> AsMut<BumpObject> for BumpFuture<O> {
-    fn as_mut(&mut self) -> &mut BumpObject {
-        &mut self.inner
+impl<O> From<BumpBox<dyn Future<Output = O> + Send>> for BumpFuture<O> {
+    fn from(fut: BumpBox<dyn Future<Output = O> + Send>) -> Self {
+        Self { inner: fut }
     }
 }
 impl<O> Future for BumpFuture<O> {
     type Output = O;
 
-    fn poll(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Self::Output> {
-        (self.poll_fn)(self, cx)
+    #[inline]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: the Future is never moved out of BumpBox, same as Pin<BumpBox<_>>
+        unsafe { Pin::new_unchecked(&mut *self.inner) }.poll(cx)
     }
 }
 
@@ -67,6 +66,11 @@ pub trait BumpFutureExt<O> {
     fn bumped<T>(self, alloc: &T) -> BumpFuture<O>
     where
         T: BumpAlloc;
+
+    /// like [`BumpFutureExt::bumped`], but return AllocError instead of abort when allocation failed
+    fn try_bumped<T>(self, alloc: &T) -> Result<BumpFuture<O>, AllocError>
+    where
+        T: BumpAlloc;
 }
 impl<F, O> BumpFutureExt<O> for F
 where
@@ -76,17 +80,69 @@ where
     where
         T: BumpAlloc,
     {
-        let obj = alloc.alloc(self);
-        let poll_fn = poll_future::<BumpFuture<O>, F>;
-        BumpFuture::new(obj, poll_fn)
+        BumpFuture::from_box(alloc.alloc_typed(self))
+    }
+
+    fn try_bumped<T>(self, alloc: &T) -> Result<BumpFuture<O>, AllocError>
+    where
+        T: BumpAlloc,
+    {
+        Ok(BumpFuture::from_box(alloc.try_alloc_typed(self)?))
+    }
+}
+
+/// Type erased Future which may be !Send,stored in Bump
+/// It wraps a `LocalBumpBox<dyn Future>`, !Send, used within current thread runtime or LocalSet
+pub struct LocalBumpFuture<O> {
+    // pinned once wrapped, never moved out, BumpBox is a pointer, so Self is Unpin
+    inner: LocalBumpBox<dyn Future<Output = O>>,
+}
+impl<O> From<LocalBumpBox<dyn Future<Output = O>>> for LocalBumpFuture<O> {
+    fn from(fut: LocalBumpBox<dyn Future<Output = O>>) -> Self {
+        Self { inner: fut }
+    }
+}
+impl<O> Future for LocalBumpFuture<O> {
+    type Output = O;
+
+    #[inline]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: the Future is never moved out of BumpBox, same as Pin<BumpBox<_>>
+        unsafe { Pin::new_unchecked(&mut *self.inner) }.poll(cx)
+    }
+}
+
+/// Future extension trait for convert type impl Future(may be !Send) into LocalBumpFuture
+pub trait LocalBumpFutureExt<O> {
+    /// take a LocalBumpAlloc reference as input,and will convert self into LocalBumpFuture
+    fn bumped_local(self, alloc: &LocalBumpAlloc) -> LocalBumpFuture<O>;
+}
+impl<F, O> LocalBumpFutureExt<O> for F
+where
+    F: Future<Output = O> + 'static,
+{
+    fn bumped_local(self, alloc: &LocalBumpAlloc) -> LocalBumpFuture<O> {
+        let fut = alloc.alloc_typed(self);
+        crate::unsize_bump_box!(fut, dyn Future<Output = O>).into()
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::util::{check_send, check_unpin};
+    use std::{
+        future::Future,
+        pin::Pin,
+        rc::Rc,
+        task::{Context, Poll, Waker},
+    };
+
+    use crate::{
+        alloc::{BumpAlloc, HeapAlloc, LocalBumpAlloc, PoolBumpAlloc},
+        bump::pool::BumpPool,
+        util::{check_send, check_unpin},
+    };
 
-    use super::BumpFuture;
+    use super::{BumpFuture, BumpFutureExt, LocalBumpFuture, LocalBumpFutureExt};
 
     #[test]
     fn test_future_bounds() {
@@ -98,5 +154,113 @@ mod test {
 
         // ensure BumpFuture is !Sync,following code should not compile
         // check_sync::<BumpFuture<()>>();
+
+        //ensure LocalBumpFuture is Unpin
+        check_unpin::<LocalBumpFuture<()>>();
+
+        // ensure LocalBumpFuture is !Send,following code should not compile
+        // check_send::<LocalBumpFuture<()>>();
+    }
+
+    #[tokio::test]
+    async fn test_try_bumped() {
+        let pool = BumpPool::new(1, 1024);
+        let bump = pool.take();
+        // no more chunk allowed beyond the first one
+        bump.set_allocation_limit(Some(bump.allocated_bytes()));
+        let alloc = PoolBumpAlloc::new(bump);
+
+        let fut = async move { 32_u32 }.try_bumped(&alloc);
+        assert_eq!(fut.unwrap().await, 32);
+
+        let buf = [0_u8; 4096];
+        let fut = async move { buf.len() }.try_bumped(&alloc);
+        let err = fut.err().expect("should fail");
+        assert!(err.layout().size() >= 4096);
+    }
+
+    /// Unpin Future return Pending once, then Ready with the value it holds
+    struct YieldWith<T>(Option<T>, bool);
+    impl<T> YieldWith<T> {
+        fn new(val: T) -> Self {
+            Self(Some(val), false)
+        }
+    }
+    impl<T: Unpin> Future for YieldWith<T> {
+        type Output = T;
+
+        fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<T> {
+            if self.1 {
+                return Poll::Ready(self.0.take().expect("polled after ready"));
+            }
+            self.1 = true;
+            Poll::Pending
+        }
+    }
+
+    /// !Unpin Future holds a reference into itself across await
+    async fn self_ref<const N: usize>() -> usize {
+        let buf = [1_u8; N];
+        let slice = &buf[..];
+        YieldWith::new(()).await;
+        slice.iter().map(|val| *val as usize).sum()
+    }
+
+    /// poll manually without executor, so it runs under Miri,
+    /// the Future is moved between the two polls
+    fn poll_twice<F: Future + Unpin>(mut fut: F) -> F::Output {
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+        let mut fut = Box::new(fut);
+        match Pin::new(&mut fut).poll(&mut cx) {
+            Poll::Ready(val) => val,
+            Poll::Pending => panic!("should be ready"),
+        }
+    }
+
+    #[test]
+    fn test_not_unpin() {
+        let pool = BumpPool::new(1, 1024);
+        let alloc = PoolBumpAlloc::new(pool.take());
+
+        // !Unpin Future stays in place in Bump or on heap, BumpFuture can be moved between polls
+        assert_eq!(poll_twice(self_ref::<256>().bumped(&alloc)), 256);
+        assert_eq!(poll_twice(self_ref::<4>().bumped(&alloc)), 4);
+        let fut = self_ref::<256>().bumped(&HeapAlloc);
+        assert!(fut.is_boxed());
+        assert_eq!(poll_twice(fut), 256);
+
+        // dropped while suspended
+        let mut fut = self_ref::<256>().bumped(&alloc);
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn test_from_bump_box() {
+        let pool = BumpPool::new(1, 1024);
+        let alloc = PoolBumpAlloc::new(pool.take());
+
+        // BumpFuture is the BumpBox<dyn Future> unsized by the macro
+        let fut = alloc.alloc_typed(self_ref::<16>());
+        let fut = crate::unsize_bump_box!(fut, dyn Future<Output = usize> + Send);
+        let fut = BumpFuture::from(fut);
+        assert!(!fut.is_boxed());
+        drop(alloc);
+        assert_eq!(poll_twice(fut), 16);
+        assert_eq!(pool.len(), 1);
+
+        let alloc = LocalBumpAlloc::new(pool.take());
+        let counter = Rc::new(());
+        let val = counter.clone();
+        let fut = async move {
+            YieldWith::new(()).await;
+            Rc::strong_count(&val)
+        }
+        .bumped_local(&alloc);
+        drop(alloc);
+        assert_eq!(poll_twice(fut), 2);
+        assert_eq!(Rc::strong_count(&counter), 1);
+        assert_eq!(pool.len(), 1);
     }
 }
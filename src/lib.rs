@@ -24,6 +24,8 @@
 //!
 //! It seems that about 5%-10% improvements of Req/Sec when use [BumpFuture](`crate::future::BumpFuture`).
 //!
+//! Benchmarks comparing with BoxFuture and StackFuture are in benches dir, run with `cargo bench --features hyper`.
+//!
 //! This is inspired by [StackFuture](https://github.com/microsoft/stackfuture). The limit of it is that can not use StackFuture inside another StackFuture.
 //!
 //! # Examples
@@ -31,17 +33,13 @@
 //! use bump_future::bump::pool::PoolConfig;
 //! use bump_future::future::BumpFutureExt;
 //! use bump_future::alloc_mod;
-//! use bump_future::tokio;
 //! use std::time::Duration;
 //!
 //! alloc_mod!(bump_alloc);
 //!
 //! #[tokio::main]
 //! async fn main() {
-//!     let conf = PoolConfig {
-//!         pool_capacity: 8,
-//!         bump_capacity: 1024,
-//!     };
+//!     let conf = PoolConfig::new(8, 1024);
 //!     let _ = bump_alloc::init(conf);
 //!     
 //!     let fut = bump_alloc::set_bump(async move {
@@ -60,15 +58,39 @@
 //! }
 //! ```
 //! For a real hyper server example, see examples dir.
+//!
+//! # Features
+//! - `tokio`(default): `spawn_trimmer` of [`alloc_mod!`] and [`TokioSpawner`](crate::bump::recycle::TokioSpawner).
+//!   Without it, [`alloc_mod!`] and [`PoolBumpAlloc`](crate::alloc::PoolBumpAlloc) work with any executor or none,
+//!   Bump is recycled according to [`RecycleStrategy`](crate::bump::recycle::RecycleStrategy)
+//! - `bytes`: convert [`BumpBytes`](crate::collections::BumpBytes) into [`bytes::Bytes`](https://docs.rs/bytes/latest/bytes/struct.Bytes.html) without copy
+//! - `allocator-api2`: [`BumpAllocator`](crate::alloc::BumpAllocator) handle usable with allocator_api2 collections
+//! - `tower`: [`BumpLayer`](crate::service::BumpLayer) and [`BumpServiceExt`](crate::service::BumpServiceExt) store response future of tower Service in Bump
+//! - `hyper`: [`bump_service_fn`](crate::server::bump_service_fn) and [`serve_connection`](crate::server::serve_connection) scope every hyper request in its own Bump
+//! - `axum`: [`RequestBump`](crate::extract::RequestBump) extractor allocates in the Bump of request scoped by [`BumpLayer`](crate::service::BumpLayer) middleware
+//! - `metrics`: emit pool metrics with [metrics](https://docs.rs/metrics/latest/metrics/) crate
+//! - `debug-checks`: no effect, [`BumpFuture`](crate::future::BumpFuture) and [`BumpStream`](crate::stream::BumpStream)
+//!   are polled through the vtable of `BumpBox<dyn Trait>`, there is no type to check, kept for compatibility
 
 #![allow(dead_code)]
-#![allow(unstable_name_collisions)]
 pub mod alloc;
+pub mod boxed;
 pub mod bump;
+pub mod collections;
+pub mod error;
+#[cfg(feature = "axum")]
+pub mod extract;
 pub mod future;
 pub mod obj;
+pub mod scope;
+#[cfg(feature = "hyper")]
+pub mod server;
+#[cfg(feature = "tower")]
+pub mod service;
+pub mod stream;
 pub(crate) mod util;
 
+#[cfg(feature = "tokio")]
 pub mod tokio {
     //! re-exports tokio
     pub use tokio::*;
@@ -79,116 +101,324 @@ pub mod once_cell {
 }
 
 /// Generate a api mod to use BumpFuture
-/// Every mod generated by this macro has a Bump pool
+/// Every mod generated by this macro has a Bump pool,
+/// the allocator is associated with Future by [`ScopedFuture`](crate::scope::ScopedFuture),
+/// so it works with any executor
 #[macro_export]
 macro_rules! alloc_mod {
     ( $vis:vis $name:ident ) => {
         $vis mod $name {
             use std::future::Future;
 
-            use $crate::alloc::TokioBumpAlloc;
+            use $crate::alloc::{HeapAlloc, LocalBumpAlloc, PoolBumpAlloc};
+            use $crate::future::{BumpFuture, BumpFutureExt};
             use $crate::once_cell::sync::{Lazy, OnceCell};
-            use $crate::tokio::{
-                runtime::Handle,
-                task::futures::TaskLocalFuture,
-                task_local,
-            };
+            use $crate::scope::{with_scope, BumpScope, ScopeKey, ScopeSlot, ScopedFuture};
 
             use $crate::bump::{
+                    connection::ConnectionBump,
                     pool::{BumpPool, PoolConfig},
                 };
+            use $crate::error::PoolExhausted;
             static POOL_CONFIG: OnceCell<PoolConfig> = OnceCell::new();
             static POOL: Lazy<BumpPool> = Lazy::new(|| {
                 let conf = POOL_CONFIG.get();
                 assert!(conf.is_some(),"mod '{}' not init yet",stringify!($name));
                 let conf = conf.unwrap();
-                BumpPool::new(conf.pool_capacity, conf.bump_capacity)
+                BumpPool::named(stringify!($name), conf)
             });
-            task_local! {
-                pub static TASK_ALLOC: TokioBumpAlloc;
+            thread_local! {
+                /// PoolBumpAlloc of the Future returned by [`set_bump`], set when it is polled
+                pub static SCOPE_ALLOC: ScopeSlot<PoolBumpAlloc> = const { ScopeSlot::new() };
+                /// LocalBumpAlloc of the Future returned by [`set_local_bump`], set when it is polled
+                pub static LOCAL_SCOPE_ALLOC: ScopeSlot<LocalBumpAlloc> = const { ScopeSlot::new() };
+                pub static PARENT_ALLOC: ScopeSlot<PoolBumpAlloc> = const { ScopeSlot::new() };
+            }
+            $crate::__alloc_mod_task_local!();
+
+            /// Future returned by [`set_bump`], the PoolBumpAlloc is accessible when it polled
+            pub type ScopedBumpFuture<F> = ScopedFuture<PoolBumpAlloc, F>;
+            /// Future returned by [`set_local_bump`], the LocalBumpAlloc is accessible when it polled
+            pub type ScopedLocalBumpFuture<F> = ScopedFuture<LocalBumpAlloc, F>;
+
+            /// [`BumpScope`] of this mod, used by generic adapters e.g. `BumpLayer<Scope>`
+            #[derive(Debug, Clone, Copy, Default)]
+            pub struct Scope;
+            impl BumpScope for Scope {
+                fn key() -> &'static ScopeKey<PoolBumpAlloc> {
+                    return &SCOPE_ALLOC;
+                }
+                fn new_alloc() -> PoolBumpAlloc {
+                    return PoolBumpAlloc::new(POOL.take());
+                }
+                fn connection() -> ConnectionBump {
+                    return POOL.connection();
+                }
             }
 
             /// Init with config
             pub fn init(config: PoolConfig) -> Result<(), PoolConfig> {
                 return POOL_CONFIG.set(config);
             }
-            /// Access the TokioBumpAlloc associate with the current task
-            /// must call within async context otherwise will panic
-            /// if no TokioBumpAlloc with current task,it will take one from pool
+            /// Access the PoolBumpAlloc associate with the current Future
+            /// if no PoolBumpAlloc with current Future,it will take one from pool
             pub fn with_task_or_new<F, R>(func: F) -> R
             where
-                F: FnOnce(&TokioBumpAlloc) -> R,
+                F: FnOnce(&PoolBumpAlloc) -> R,
             {
-                let ret = TASK_ALLOC.try_with(|_alloc| ());
+                let mut func = Some(func);
+                let ret = with_task(|alloc| (func.take().unwrap())(alloc));
                 match ret {
-                    Ok(_) => {
-                        return TASK_ALLOC.try_with(func).expect("should not be Err");
+                    Some(ret) => {
+                        return ret;
                     }
-                    Err(_err) => {
+                    None => {
                         let bump = POOL.take();
-                        let alloc = TokioBumpAlloc::new(Handle::current(), bump);
-                        return func(&alloc);
+                        let alloc = PoolBumpAlloc::new(bump);
+                        return (func.take().unwrap())(&alloc);
                     }
                 }
             }
+            $crate::__alloc_mod_trimmer!(POOL);
             /// Return pool reference
             pub fn pool() -> &'static BumpPool {
                 return &POOL;
             }
-            /// Access the TokioBumpAlloc associate with the current task
-            /// must call within async context otherwise will panic
-            /// if no TokioBumpAlloc with current task, it will return None
+            /// Access the PoolBumpAlloc associate with the current Future,
+            /// it can be called in sync code as long as polled inside [`set_bump`],
+            /// or inside the scope of tokio task local `TASK_ALLOC` with `tokio` feature
+            /// if no PoolBumpAlloc with current Future, it will return None
             pub fn with_task<F, R>(func: F) -> Option<R>
             where
-                F: FnOnce(&TokioBumpAlloc) -> R,
+                F: FnOnce(&PoolBumpAlloc) -> R,
             {
-                let ret = TASK_ALLOC.try_with(|_alloc| ());
+                let mut func = Some(func);
+                let ret = with_scope(&SCOPE_ALLOC, |alloc| (func.take().unwrap())(alloc));
+                match (ret, func) {
+                    (Some(ret), _) => {
+                        return Some(ret);
+                    }
+                    (None, Some(func)) => {
+                        return with_task_local(func);
+                    }
+                    (None, None) => {
+                        return None;
+                    }
+                }
+            }
+
+            /// Convert the Future input into BumpFuture with the PoolBumpAlloc associate with the current Future
+            /// if no PoolBumpAlloc with current Future, the Future will be allocated on global heap,
+            /// and counted in `heap_fallbacks` of [`BumpPool::stats`]
+            pub fn bumped<F>(fut: F) -> BumpFuture<F::Output>
+            where
+                F: Future + Send + 'static,
+            {
+                let mut fut = Some(fut);
+                let ret = with_task(|alloc| fut.take().unwrap().bumped(alloc));
                 match ret {
-                    Ok(_) => {
-                        let ret = TASK_ALLOC.try_with(func).expect("should not be Err");
+                    Some(ret) => {
+                        return ret;
+                    }
+                    None => {
+                        POOL.record_heap_fallback();
+                        return fut.take().unwrap().bumped(&HeapAlloc);
+                    }
+                }
+            }
+
+            /// Set a PoolBumpAlloc with the Future input
+            /// when the Future polled , it can access the PoolBumpAlloc
+            pub fn set_bump<F>(fut: F) -> ScopedBumpFuture<F>
+            where
+                F: Future,
+            {
+                let bump = POOL.take();
+                let alloc = PoolBumpAlloc::new(bump);
+                return ScopedFuture::new(&SCOPE_ALLOC, alloc, fut);
+            }
+
+            /// Create a ConnectionBump owns one Bump for a keep-alive connection
+            pub fn connection() -> ConnectionBump {
+                return POOL.connection();
+            }
+
+            /// Like [`set_bump`], but take the Bump of connection for the request generation,
+            /// it is reset and release back to the connection when the request completed
+            pub fn set_connection_bump<F>(conn: &ConnectionBump, fut: F) -> ScopedBumpFuture<F>
+            where
+                F: Future,
+            {
+                let alloc = PoolBumpAlloc::new(conn.take());
+                return ScopedFuture::new(&SCOPE_ALLOC, alloc, fut);
+            }
+
+            /// Like [`set_bump`], but return PoolExhausted when `pool_capacity` Bumps of pool already in use
+            /// instead of create a new Bump, so caller can shed load under memory pressure
+            pub fn try_set_bump<F>(fut: F) -> Result<ScopedBumpFuture<F>, PoolExhausted>
+            where
+                F: Future,
+            {
+                let bump = POOL.try_take()?;
+                let alloc = PoolBumpAlloc::new(bump);
+                return Ok(ScopedFuture::new(&SCOPE_ALLOC, alloc, fut));
+            }
+
+            /// Take a child Bump from pool for a sub operation, it is recycled as soon as the Future completed
+            /// independent of the Bump of the enclosing [`set_bump`] or [`scope`],
+            /// which is still accessible by [`with_parent`] for values must outlive the sub operation
+            pub fn scope<F>(fut: F) -> ScopedBumpFuture<F>
+            where
+                F: Future,
+            {
+                let bump = POOL.take();
+                let alloc = PoolBumpAlloc::new(bump);
+                return ScopedFuture::child(&SCOPE_ALLOC, &PARENT_ALLOC, alloc, fut);
+            }
+
+            /// Access the PoolBumpAlloc of the enclosing scope, when the current Future is polled inside [`scope`]
+            /// if not inside [`scope`] or no enclosing PoolBumpAlloc, it will return None
+            pub fn with_parent<F, R>(func: F) -> Option<R>
+            where
+                F: FnOnce(&PoolBumpAlloc) -> R,
+            {
+                return with_scope(&PARENT_ALLOC, func);
+            }
+
+            /// Access the LocalBumpAlloc associate with the current Future,
+            /// or the tokio task local `LOCAL_TASK_ALLOC` with `tokio` feature
+            /// if no LocalBumpAlloc with current Future, it will return None
+            pub fn with_local_task<F, R>(func: F) -> Option<R>
+            where
+                F: FnOnce(&LocalBumpAlloc) -> R,
+            {
+                let mut func = Some(func);
+                let ret = with_scope(&LOCAL_SCOPE_ALLOC, |alloc| (func.take().unwrap())(alloc));
+                match (ret, func) {
+                    (Some(ret), _) => {
                         return Some(ret);
                     }
-                    Err(_err) => {
+                    (None, Some(func)) => {
+                        return with_local_task_local(func);
+                    }
+                    (None, None) => {
                         return None;
                     }
                 }
             }
 
-            /// Set a TokioBumpAlloc with the Future input
-            /// when the Future polled , it can access the TokioBumpAlloc
-            pub fn set_bump<F>(fut: F) -> TaskLocalFuture<TokioBumpAlloc, F>
+            /// Set a LocalBumpAlloc with the Future input, the Future may be !Send
+            /// when the Future polled , it can access the LocalBumpAlloc
+            /// the Bump is released back to pool on the current thread, no task spawned
+            pub fn set_local_bump<F>(fut: F) -> ScopedLocalBumpFuture<F>
             where
                 F: Future,
             {
                 let bump = POOL.take();
-                let alloc = TokioBumpAlloc::new(Handle::current(), bump);
-                let fut = TASK_ALLOC.scope(alloc, fut);
-                return fut;
+                let alloc = LocalBumpAlloc::new(bump);
+                return ScopedFuture::new(&LOCAL_SCOPE_ALLOC, alloc, fut);
             }
                 }
             };
 }
 
+/// Generate `spawn_trimmer` for [`alloc_mod!`] when `tokio` feature enabled
+#[cfg(feature = "tokio")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __alloc_mod_trimmer {
+    ( $pool:ident ) => {
+        /// Spawn a task to call [`BumpPool::trim`] every interval,
+        /// so idle Bump instances beyond `min_idle` are released after load peak
+        pub fn spawn_trimmer(interval: std::time::Duration) -> $crate::tokio::task::JoinHandle<()> {
+            return $crate::tokio::runtime::Handle::current().spawn(async move {
+                let mut interval = $crate::tokio::time::interval(interval);
+                loop {
+                    interval.tick().await;
+                    $pool.trim();
+                }
+            });
+        }
+    };
+}
+#[cfg(not(feature = "tokio"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __alloc_mod_trimmer {
+    ( $pool:ident ) => {};
+}
+
+/// Generate tokio task local `TASK_ALLOC` and `LOCAL_TASK_ALLOC` for [`alloc_mod!`] when `tokio` feature enabled
+#[cfg(feature = "tokio")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __alloc_mod_task_local {
+    () => {
+        $crate::tokio::task_local! {
+            /// TokioBumpAlloc of tokio task, set with `TASK_ALLOC.scope(alloc, fut)`,
+            /// it is accessible by [`with_task`] like the PoolBumpAlloc set by [`set_bump`]
+            pub static TASK_ALLOC: $crate::alloc::TokioBumpAlloc;
+            /// LocalBumpAlloc of tokio task, set with `LOCAL_TASK_ALLOC.scope(alloc, fut)`,
+            /// it is accessible by [`with_local_task`]
+            pub static LOCAL_TASK_ALLOC: $crate::alloc::LocalBumpAlloc;
+        }
+        fn with_task_local<F, R>(func: F) -> Option<R>
+        where
+            F: FnOnce(&PoolBumpAlloc) -> R,
+        {
+            return TASK_ALLOC.try_with(|alloc| func(alloc)).ok();
+        }
+        fn with_local_task_local<F, R>(func: F) -> Option<R>
+        where
+            F: FnOnce(&LocalBumpAlloc) -> R,
+        {
+            return LOCAL_TASK_ALLOC.try_with(func).ok();
+        }
+    };
+}
+#[cfg(not(feature = "tokio"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __alloc_mod_task_local {
+    () => {
+        fn with_task_local<F, R>(_func: F) -> Option<R>
+        where
+            F: FnOnce(&PoolBumpAlloc) -> R,
+        {
+            return None;
+        }
+        fn with_local_task_local<F, R>(_func: F) -> Option<R>
+        where
+            F: FnOnce(&LocalBumpAlloc) -> R,
+        {
+            return None;
+        }
+    };
+}
+
 #[cfg(test)]
 mod test {
 
-    use std::time::Duration;
+    use std::{
+        cell::RefCell,
+        future::Future,
+        pin::pin,
+        rc::Rc,
+        task::{Context, Poll, Waker},
+        time::Duration,
+    };
 
-    use tokio::io::copy;
+    use tokio::{io::copy, task::LocalSet};
 
-    use crate::alloc_mod;
     use crate::bump::pool::PoolConfig;
-    use crate::future::BumpFutureExt;
+    use crate::future::{BumpFuture, BumpFutureExt, LocalBumpFutureExt};
 
     // generate a mod of name "bump_alloc"
     alloc_mod!(bump_alloc);
 
     #[tokio::test]
     async fn test_bump_future() {
-        let conf = PoolConfig {
-            pool_capacity: 8,
-            bump_capacity: 1024,
-        };
+        let conf = PoolConfig::new(8, 1024);
         let _ = bump_alloc::init(conf);
 
         //after init ,pool len should be 8
@@ -198,6 +428,12 @@ mod test {
         test_set_bump_multi_times().await;
         test_not_unpin_box().await;
         test_not_unpin_bump().await;
+        test_local_bump_future().await;
+        test_heap_fallback().await;
+        test_sync_with_task();
+        test_child_scope().await;
+        #[cfg(feature = "tokio")]
+        test_task_local().await;
     }
 
     async fn test_bump_future_simple() {
@@ -288,4 +524,102 @@ mod test {
         let rslt = fut.await;
         assert_eq!(rslt, 123);
     }
+
+    // test future which is !Send with LocalBumpFuture
+    async fn test_local_bump_future() {
+        // wait Bump used by previous test recycled
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let local = LocalSet::new();
+        let rslt = local
+            .run_until(async move {
+                let fut = bump_alloc::set_local_bump(async move {
+                    let state = Rc::new(RefCell::new(0_u32));
+                    let fut = bump_alloc::with_local_task(|alloc| {
+                        let state = state.clone();
+                        async move {
+                            tokio::time::sleep(Duration::from_millis(10)).await;
+                            *state.borrow_mut() += 32;
+                        }
+                        .bumped_local(alloc)
+                    });
+                    fut.unwrap().await;
+                    let val = *state.borrow();
+                    val
+                });
+                // Bump taken from pool
+                assert_eq!(bump_alloc::pool().len(), 7);
+                tokio::task::spawn_local(fut).await.unwrap()
+            })
+            .await;
+        assert_eq!(rslt, 32);
+        // Bump released back to pool without wait
+        assert_eq!(bump_alloc::pool().len(), 8);
+    }
+
+    // test BumpFuture allocated on heap when no Bump set
+    async fn test_heap_fallback() {
+        let fallbacks = bump_alloc::pool().stats().heap_fallbacks;
+        let fut = bump_alloc::bumped(async move { 32_u32 });
+        assert!(fut.is_boxed());
+        assert_eq!(fut.await, 32);
+        assert_eq!(bump_alloc::pool().stats().heap_fallbacks, fallbacks + 1);
+
+        let fut = bump_alloc::set_bump(async move {
+            let fut = bump_alloc::bumped(async move { 32_u32 });
+            assert!(!fut.is_boxed());
+            fut.await
+        });
+        assert_eq!(fut.await, 32);
+        assert_eq!(bump_alloc::pool().stats().heap_fallbacks, fallbacks + 1);
+    }
+
+    // test with_task called from sync code, polled without any executor
+    fn test_sync_with_task() {
+        fn sync_bumped() -> Option<BumpFuture<u32>> {
+            bump_alloc::with_task(|alloc| async move { 32_u32 }.bumped(alloc))
+        }
+        assert!(sync_bumped().is_none());
+
+        let fut = bump_alloc::set_bump(async move { sync_bumped().unwrap().await });
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(pin!(fut).poll(&mut cx), Poll::Ready(32));
+        // Bump released when completed
+        assert_eq!(bump_alloc::pool().len(), 8);
+    }
+
+    // test child Bump recycled when sub operation completed, parent still reachable
+    async fn test_child_scope() {
+        let fut = bump_alloc::set_bump(async move {
+            assert!(bump_alloc::with_parent(|_alloc| ()).is_none());
+            let (outlive, local) = bump_alloc::scope(async move {
+                // parent and child both taken
+                assert_eq!(bump_alloc::pool().len(), 6);
+                let local = bump_alloc::with_task(|alloc| async move { 1_u32 }.bumped(alloc));
+                let outlive = bump_alloc::with_parent(|alloc| async move { 32_u32 }.bumped(alloc));
+                (outlive.unwrap(), local.unwrap().await)
+            })
+            .await;
+            // child recycled, parent still in use
+            assert_eq!(bump_alloc::pool().len(), 7);
+            assert!(bump_alloc::with_parent(|_alloc| ()).is_none());
+            assert!(bump_alloc::with_task(|_alloc| ()).is_some());
+            outlive.await + local
+        });
+        assert_eq!(fut.await, 33);
+        assert_eq!(bump_alloc::pool().len(), 8);
+    }
+
+    // test allocator scoped with tokio task local is accessible like set_bump
+    #[cfg(feature = "tokio")]
+    async fn test_task_local() {
+        use crate::alloc::{PoolBumpAlloc, TokioBumpAlloc};
+
+        let alloc = PoolBumpAlloc::new(bump_alloc::pool().take());
+        let fut = bump_alloc::TASK_ALLOC.scope(TokioBumpAlloc::from(alloc), async move {
+            let fut = bump_alloc::with_task(|alloc| async move { 32_u32 }.bumped(alloc));
+            fut.unwrap().await
+        });
+        assert_eq!(fut.await, 32);
+        assert_eq!(bump_alloc::pool().len(), 8);
+    }
 }
@@ -13,49 +13,137 @@
 //! Type for object stored in [Bump](`bumpalo::Bump`)
 
 use bumpalo::Bump;
-use std::{
-    alloc::Layout, any::TypeId, cell::Cell, marker::PhantomData, num::NonZeroUsize, ptr::NonNull,
-};
-
-use sptr::Strict;
+use std::{alloc::Layout, any::TypeId, cell::Cell, marker::PhantomData, ptr::NonNull};
 
 use crate::{
-    bump::BumpRef,
-    util::{addr_to_ptr, drop_by_addr},
+    bump::{pool::LimitPolicy, BumpRef, LocalBumpRef},
+    error::{AllocError, AllocErrorKind},
+    util::{drop_box_erased, drop_erased},
 };
 
 /// Smart pointer point to object stored in Bump
 /// It use TypeId to check when downcast in runtime, so only 'static type supported
 pub struct UnsafeObject {
-    addr: Option<NonZeroUsize>,
+    ptr: Option<NonNull<()>>,
     type_id: TypeId,
-    drop_fn: unsafe fn(NonZeroUsize),
+    drop_fn: unsafe fn(NonNull<()>),
+    boxed: bool,
     // Self only require input type is Send, we must ensure Self is !sync,
     _p: PhantomData<Cell<()>>,
 }
+// constructors require input type is Send, or the caller ensures it never sent to another thread
+unsafe impl Send for UnsafeObject {}
 impl UnsafeObject {
     /// # Safety
     /// the safety depends on Bump used to create this object not reset or droped while this object is still live
     pub unsafe fn new<T>(bump: &Bump, inner: T) -> Self
     where
         T: Send + 'static,
+    {
+        Self::new_local(bump, inner)
+    }
+
+    /// # Safety
+    /// Same as [`UnsafeObject::new`], and because input type may be !Send,
+    /// the caller must ensure this object is never sent to another thread,
+    /// e.g. by wrapping it in a !Send type like [`LocalBumpObject`]
+    pub unsafe fn new_local<T>(bump: &Bump, inner: T) -> Self
+    where
+        T: 'static,
     {
         let layout = Layout::new::<T>();
         let ptr = bump.alloc_layout(layout);
-        let ptr = unsafe {
-            let ptr = ptr.cast::<T>();
-            ptr.as_ptr().write(inner);
-            ptr
+        Self::write(ptr, inner)
+    }
+
+    /// # Safety
+    /// Same as [`UnsafeObject::new`], but return AllocError instead of abort when allocation failed
+    pub unsafe fn try_new<T>(bump: &Bump, inner: T) -> Result<Self, AllocError>
+    where
+        T: Send + 'static,
+    {
+        Self::try_new_local(bump, inner)
+    }
+
+    /// # Safety
+    /// Same as [`UnsafeObject::new_local`], but return AllocError instead of abort when allocation failed
+    pub unsafe fn try_new_local<T>(bump: &Bump, inner: T) -> Result<Self, AllocError>
+    where
+        T: 'static,
+    {
+        let ptr = try_alloc_layout(bump, Layout::new::<T>())?;
+        Ok(Self::write(ptr, inner))
+    }
+
+    /// # Safety
+    /// Same as [`UnsafeObject::try_new_local`], and when allocation exceed the limit of Bump
+    /// and policy is [`LimitPolicy::Heap`], object will be allocated on global heap
+    pub(crate) unsafe fn try_new_with_policy<T>(
+        bump: &Bump,
+        inner: T,
+        policy: LimitPolicy,
+    ) -> Result<Self, AllocError>
+    where
+        T: 'static,
+    {
+        let (ptr, boxed) = alloc_with_policy(bump, inner, policy)?;
+        Ok(Self::from_raw(ptr, boxed))
+    }
+
+    /// # Safety
+    /// ptr must point to a valid T, allocated on global heap by Box when boxed,
+    /// otherwise in Bump, and owned by the returned object
+    pub(crate) unsafe fn from_raw<T>(ptr: NonNull<T>, boxed: bool) -> Self
+    where
+        T: 'static,
+    {
+        let drop_fn = match boxed {
+            true => drop_box_erased::<T>,
+            false => drop_erased::<T>,
         };
-        let addr = ptr.as_ptr().addr();
         Self {
-            addr: Some(NonZeroUsize::new(addr).expect("addr shoud not be zero")),
+            ptr: Some(ptr.cast::<()>()),
             type_id: TypeId::of::<T>(),
-            drop_fn: drop_by_addr::<T>,
+            drop_fn,
+            boxed,
             _p: PhantomData,
         }
     }
 
+    /// Allocate object on global heap instead of Bump,
+    /// used as fallback when Bump can not be used
+    pub fn new_boxed<T>(inner: T) -> Self
+    where
+        T: Send + 'static,
+    {
+        unsafe { Self::new_boxed_local(inner) }
+    }
+
+    /// # Safety
+    /// Input type may be !Send, the caller must ensure this object is never sent to another thread
+    pub unsafe fn new_boxed_local<T>(inner: T) -> Self
+    where
+        T: 'static,
+    {
+        let ptr = NonNull::from(Box::leak(Box::new(inner)));
+        Self::from_raw(ptr, true)
+    }
+
+    /// Check if this object is allocated on global heap instead of Bump
+    #[inline]
+    pub fn is_boxed(&self) -> bool {
+        self.boxed
+    }
+
+    /// write inner into memory pointed by ptr, which must be allocated with layout of T
+    #[inline]
+    unsafe fn write<T>(ptr: NonNull<u8>, inner: T) -> Self
+    where
+        T: 'static,
+    {
+        Self::from_raw(write_ptr(ptr, inner), false)
+    }
+
     /// Check if this object is of type T
     #[inline]
     pub fn is<T>(&self) -> bool
@@ -75,9 +163,7 @@ impl UnsafeObject {
         T: 'static,
     {
         if self.is::<T>() {
-            let ptr: NonNull<T> = addr_to_ptr::<T>(*self.addr.as_ref().unwrap());
-            let inner = &*ptr.as_ptr();
-            Some(inner)
+            Some(self.downcast_ref_unchecked::<T>())
         } else {
             None
         }
@@ -92,34 +178,153 @@ impl UnsafeObject {
         T: 'static,
     {
         if self.is::<T>() {
-            let ptr: NonNull<T> = addr_to_ptr::<T>(*self.addr.as_ref().unwrap());
-            // 指针转换为引用
-            let inner = &mut *ptr.as_ptr();
-            Some(inner)
+            Some(self.downcast_mut_unchecked::<T>())
         } else {
             None
         }
     }
+    /// # Safety
+    /// Same as [`UnsafeObject::downcast_ref`], and this object must be of type T,
+    /// the type is not checked at runtime
+    #[inline]
+    pub unsafe fn downcast_ref_unchecked<T>(&self) -> &T
+    where
+        T: 'static,
+    {
+        let ptr = self.ptr.expect("object dropped").cast::<T>();
+        &*ptr.as_ptr()
+    }
+    /// # Safety
+    /// Same as [`UnsafeObject::downcast_mut`], and this object must be of type T,
+    /// the type is not checked at runtime
+    #[inline]
+    pub unsafe fn downcast_mut_unchecked<T>(&mut self) -> &mut T
+    where
+        T: 'static,
+    {
+        let ptr = self.ptr.expect("object dropped").cast::<T>();
+        &mut *ptr.as_ptr()
+    }
 }
 impl Drop for UnsafeObject {
     fn drop(&mut self) {
-        if let Some(addr) = self.addr.take() {
-            unsafe { (self.drop_fn)(addr) };
+        if let Some(ptr) = self.ptr.take() {
+            unsafe { (self.drop_fn)(ptr) };
         }
     }
 }
 
+/// write inner into memory pointed by ptr, which must be allocated with layout of T
+#[inline]
+unsafe fn write_ptr<T>(ptr: NonNull<u8>, inner: T) -> NonNull<T> {
+    let ptr = ptr.cast::<T>();
+    ptr.as_ptr().write(inner);
+    ptr
+}
+
+#[inline]
+pub(crate) fn try_alloc_layout(bump: &Bump, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    bump.try_alloc_layout(layout)
+        .map_err(|_err| AllocError::new(layout, alloc_error_kind(bump, layout)))
+}
+
+/// Default size of the first chunk of bumpalo, a new chunk is at least this or the size requested
+const MIN_CHUNK_SIZE: usize = 512;
+const PAGE_SIZE: usize = 4096;
+
+/// bumpalo does not tell the reason of failure, it tries new chunks within the limit left,
+/// halving down to the smallest one, which is below twice of the size requested or [`MIN_CHUNK_SIZE`],
+/// rounded up to a power of two or page size. If the limit left can hold that, a chunk within
+/// limit was refused by the global allocator, otherwise the limit is exceeded.
+/// bumpalo ignores the limit once allocated beyond it, e.g. by the first chunk
+fn alloc_error_kind(bump: &Bump, layout: Layout) -> AllocErrorKind {
+    let Some(remaining) = bump
+        .allocation_limit()
+        .and_then(|limit| limit.checked_sub(bump.allocated_bytes()))
+    else {
+        return AllocErrorKind::OutOfMemory;
+    };
+    let smallest_chunk = layout
+        .size()
+        .saturating_add(layout.align())
+        .max(MIN_CHUNK_SIZE)
+        .saturating_mul(4)
+        .saturating_add(PAGE_SIZE);
+    match remaining < smallest_chunk {
+        true => AllocErrorKind::LimitExceeded,
+        false => AllocErrorKind::OutOfMemory,
+    }
+}
+
+/// Allocate inner in Bump, when allocation exceed the limit of Bump and policy is
+/// [`LimitPolicy::Heap`], allocate on global heap instead, return the pointer and if it is boxed
+/// # Safety
+/// the returned pointer is valid only when Bump not reset or droped
+pub(crate) unsafe fn alloc_with_policy<T>(
+    bump: &Bump,
+    inner: T,
+    policy: LimitPolicy,
+) -> Result<(NonNull<T>, bool), AllocError> {
+    match try_alloc_layout(bump, Layout::new::<T>()) {
+        Ok(ptr) => Ok((write_ptr(ptr, inner), false)),
+        Err(err) if err.kind() == AllocErrorKind::LimitExceeded && policy == LimitPolicy::Heap => {
+            Ok((NonNull::from(Box::leak(Box::new(inner))), true))
+        }
+        Err(err) => Err(err),
+    }
+}
+
 /// Object stored in Bump,it holds a BumpRef,so if this object is still alive,
 /// the Bump instance it is stored will not be set and release
+/// When object is allocated on global heap,it holds no BumpRef
 pub struct BumpObject {
     inner: UnsafeObject,
-    _bump_ref: BumpRef,
+    _bump_ref: Option<BumpRef>,
 }
 impl BumpObject {
     pub fn new(inner: UnsafeObject, bump_ref: BumpRef) -> Self {
+        Self {
+            inner,
+            _bump_ref: Some(bump_ref),
+        }
+    }
+    pub(crate) fn from_parts(inner: UnsafeObject, bump_ref: Option<BumpRef>) -> Self {
+        Self {
+            inner,
+            _bump_ref: bump_ref,
+        }
+    }
+    /// Allocate object on global heap, it does not hold any Bump
+    pub fn new_boxed<T>(val: T) -> Self
+    where
+        T: Send + 'static,
+    {
+        Self {
+            inner: UnsafeObject::new_boxed(val),
+            _bump_ref: None,
+        }
+    }
+    /// Check if this object is allocated on global heap instead of Bump
+    #[inline]
+    pub fn is_boxed(&self) -> bool {
+        self.inner.is_boxed()
+    }
+}
+
+/// Object stored in Bump which may be !Send,it holds a LocalBumpRef,so if this object is still alive,
+/// the Bump instance it is stored will not be set and release.
+/// This object is !Send and !Sync
+pub struct LocalBumpObject {
+    inner: UnsafeObject,
+    _bump_ref: LocalBumpRef,
+    _p: PhantomData<*const ()>,
+}
+impl LocalBumpObject {
+    pub fn new(inner: UnsafeObject, bump_ref: LocalBumpRef) -> Self {
         Self {
             inner,
             _bump_ref: bump_ref,
+            _p: PhantomData,
         }
     }
 }
@@ -135,6 +340,16 @@ pub trait BumpAny {
     fn downcast_mut<T>(&mut self) -> Option<&mut T>
     where
         T: 'static;
+    /// # Safety
+    /// this object must be of type T, the type is not checked at runtime
+    unsafe fn downcast_ref_unchecked<T>(&self) -> &T
+    where
+        T: 'static;
+    /// # Safety
+    /// this object must be of type T, the type is not checked at runtime
+    unsafe fn downcast_mut_unchecked<T>(&mut self) -> &mut T
+    where
+        T: 'static;
 }
 
 impl BumpAny for BumpObject {
@@ -158,13 +373,78 @@ impl BumpAny for BumpObject {
     {
         unsafe { self.inner.downcast_mut::<T>() }
     }
+
+    unsafe fn downcast_ref_unchecked<T>(&self) -> &T
+    where
+        T: 'static,
+    {
+        self.inner.downcast_ref_unchecked::<T>()
+    }
+
+    unsafe fn downcast_mut_unchecked<T>(&mut self) -> &mut T
+    where
+        T: 'static,
+    {
+        self.inner.downcast_mut_unchecked::<T>()
+    }
+}
+
+impl BumpAny for LocalBumpObject {
+    fn is<T>(&self) -> bool
+    where
+        T: 'static,
+    {
+        self.inner.is::<T>()
+    }
+
+    fn downcast_ref<T>(&self) -> Option<&T>
+    where
+        T: 'static,
+    {
+        unsafe { self.inner.downcast_ref::<T>() }
+    }
+
+    fn downcast_mut<T>(&mut self) -> Option<&mut T>
+    where
+        T: 'static,
+    {
+        unsafe { self.inner.downcast_mut::<T>() }
+    }
+
+    unsafe fn downcast_ref_unchecked<T>(&self) -> &T
+    where
+        T: 'static,
+    {
+        self.inner.downcast_ref_unchecked::<T>()
+    }
+
+    unsafe fn downcast_mut_unchecked<T>(&mut self) -> &mut T
+    where
+        T: 'static,
+    {
+        self.inner.downcast_mut_unchecked::<T>()
+    }
 }
 
+/// Tests of the unsafe core, Miri clean under stacked and tree borrows, checked by `.github/workflows/miri.yml`,
+/// run with `cargo +nightly miri test --lib -- obj:: future:: boxed:: scope::`, add `MIRIFLAGS="-Zmiri-tree-borrows"` for the latter
 #[cfg(test)]
 mod test {
-    use crate::util::check_send;
+    use std::{
+        alloc::Layout,
+        sync::{Arc, Mutex},
+    };
 
-    use super::UnsafeObject;
+    use bumpalo::Bump;
+
+    use crate::{
+        alloc::{BumpAlloc, PoolBumpAlloc},
+        bump::pool::BumpPool,
+        error::AllocErrorKind,
+        util::check_send,
+    };
+
+    use super::{try_alloc_layout, BumpAny, UnsafeObject};
 
     #[test]
     fn test_inner_bounds() {
@@ -173,4 +453,101 @@ mod test {
         // ensure UnsafeObject is !Sync
         // check_sync::<UnsafeObject>();
     }
+
+    #[test]
+    fn test_downcast() {
+        let bump = Bump::new();
+        let mut obj = unsafe { UnsafeObject::new(&bump, vec![1_u32, 2]) };
+        assert!(obj.is::<Vec<u32>>());
+        assert!(unsafe { obj.downcast_ref::<u32>() }.is_none());
+        unsafe { obj.downcast_mut_unchecked::<Vec<u32>>() }.push(3);
+        let val = unsafe { obj.downcast_ref::<Vec<u32>>() };
+        assert_eq!(val.map(|val| &val[..]), Some(&[1_u32, 2, 3][..]));
+
+        let obj = UnsafeObject::new_boxed(String::from("boxed"));
+        assert!(obj.is_boxed());
+        assert_eq!(unsafe { obj.downcast_ref_unchecked::<String>() }, "boxed");
+    }
+
+    #[repr(align(64))]
+    struct OverAligned(u8);
+
+    // the global allocator refuses huge chunk, Miri reports it as resource exhaustion instead
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_alloc_error_kind() {
+        let bump = Bump::new();
+        let huge = Layout::from_size_align(isize::MAX as usize / 8, 8).unwrap();
+        let err = try_alloc_layout(&bump, huge).unwrap_err();
+        assert_eq!(err.kind(), AllocErrorKind::OutOfMemory);
+        // limit left is far more than needed, so it is not the reason
+        bump.set_allocation_limit(Some(usize::MAX));
+        let err = try_alloc_layout(&bump, huge).unwrap_err();
+        assert_eq!(err.kind(), AllocErrorKind::OutOfMemory);
+
+        let small = Layout::new::<[u8; 64]>();
+        bump.set_allocation_limit(Some(bump.allocated_bytes()));
+        let err = try_alloc_layout(&bump, small).unwrap_err();
+        assert_eq!(err.kind(), AllocErrorKind::LimitExceeded);
+        bump.set_allocation_limit(Some(bump.allocated_bytes() + 1024));
+        let err = try_alloc_layout(&bump, huge).unwrap_err();
+        assert_eq!(err.kind(), AllocErrorKind::LimitExceeded);
+    }
+
+    #[test]
+    fn test_layout() {
+        let bump = Bump::new();
+        // zero sized and over aligned objects
+        let mut obj = unsafe { UnsafeObject::new(&bump, ()) };
+        assert!(unsafe { obj.downcast_mut::<()>() }.is_some());
+        let mut obj = unsafe { UnsafeObject::new(&bump, OverAligned(7)) };
+        let val = unsafe { obj.downcast_mut::<OverAligned>() }.unwrap();
+        assert_eq!((val as *mut OverAligned).align_offset(64), 0);
+        val.0 += 1;
+        assert_eq!(unsafe { obj.downcast_ref_unchecked::<OverAligned>() }.0, 8);
+    }
+
+    /// record name into log when dropped
+    struct Logged(&'static str, Arc<Mutex<Vec<&'static str>>>);
+    impl Drop for Logged {
+        fn drop(&mut self) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    #[test]
+    fn test_drop_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let bump = Bump::new();
+        let first = unsafe { UnsafeObject::new_local(&bump, Logged("first", log.clone())) };
+        let second = unsafe { UnsafeObject::new_local(&bump, Logged("second", log.clone())) };
+        let boxed = unsafe { UnsafeObject::new_boxed_local(Logged("boxed", log.clone())) };
+        drop(second);
+        drop(boxed);
+        drop(first);
+        // every object dropped exactly once, in the order its owner dropped
+        assert_eq!(*log.lock().unwrap(), ["second", "boxed", "first"]);
+    }
+
+    /// check the Bump is not recycled when dropped
+    struct CheckPool(&'static BumpPool, Vec<u32>);
+    impl Drop for CheckPool {
+        fn drop(&mut self) {
+            assert_eq!(self.0.len(), 0, "Bump recycled before object dropped");
+            assert_eq!(self.1, [1, 2, 3]);
+        }
+    }
+
+    // pool is leaked so it outlives the object, run it with `MIRIFLAGS="-Zmiri-ignore-leaks"` under Miri
+    #[test]
+    fn test_object_outlive_alloc() {
+        let pool: &'static BumpPool = Box::leak(Box::new(BumpPool::new(1, 1024)));
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let mut obj = alloc.alloc(CheckPool(pool, vec![1, 2]));
+        drop(alloc);
+        obj.downcast_mut::<CheckPool>().unwrap().1.push(3);
+        // object dropped before its BumpRef
+        drop(obj);
+        assert_eq!(pool.len(), 1);
+    }
 }
@@ -0,0 +1,373 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [`ScopedFuture`] type, associate a value with a Future by thread local,
+//! independent of executor
+use std::{
+    alloc::{handle_alloc_error, Layout},
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    ptr::NonNull,
+    task::{Context, Poll},
+    thread::LocalKey,
+};
+
+use crate::{
+    alloc::{HeapAlloc, PoolBumpAlloc},
+    boxed::BumpBox,
+    bump::connection::ConnectionBump,
+    error::AllocErrorKind,
+    future::{BumpFuture, BumpFutureExt},
+};
+
+/// Thread local slot for [`ScopedFuture`], refer to the value of the ScopedFuture being polled,
+/// so accessing it borrows nothing, and nested scopes can be polled while accessing it
+pub struct ScopeSlot<T> {
+    value: Cell<Option<NonNull<T>>>,
+}
+impl<T> ScopeSlot<T> {
+    pub const fn new() -> Self {
+        Self {
+            value: Cell::new(None),
+        }
+    }
+}
+impl<T> Default for ScopeSlot<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Thread local key of [`ScopeSlot`]
+pub type ScopeKey<T> = LocalKey<ScopeSlot<T>>;
+
+/// Access the value of the ScopedFuture being polled with key,
+/// if not polled inside any scope of key, it will return None
+pub fn with_scope<T, F, R>(key: &'static ScopeKey<T>, func: F) -> Option<R>
+where
+    T: 'static,
+    F: FnOnce(&T) -> R,
+{
+    let ptr = key.try_with(|slot| slot.value.get()).ok().flatten()?;
+    // Safety: the value is owned by the ScopedFuture up the stack, which is borrowed by poll
+    // and does not touch the value until the slot restored, so it is alive and not mutated
+    Some(func(unsafe { ptr.as_ref() }))
+}
+
+/// Future that install the value into a thread local slot for the duration of every poll,
+/// and restore the previous one after, so any code polled inside can access the value,
+/// whether sync or async, and whatever executor it runs on.
+/// When the Future completed, it will be dropped together with the value immediately.
+pub struct ScopedFuture<T: 'static, F> {
+    key: &'static ScopeKey<T>,
+    parent_key: Option<&'static ScopeKey<T>>,
+    value: Option<T>,
+    future: Option<F>,
+}
+impl<T: 'static, F> ScopedFuture<T, F> {
+    pub fn new(key: &'static ScopeKey<T>, value: T, future: F) -> Self {
+        Self {
+            key,
+            parent_key: None,
+            value: Some(value),
+            future: Some(future),
+        }
+    }
+    /// Like [`ScopedFuture::new`], but when polled, the value of the enclosing scope
+    /// is moved into parent_key slot, so it is still reachable inside this child scope
+    pub fn child(
+        key: &'static ScopeKey<T>,
+        parent_key: &'static ScopeKey<T>,
+        value: T,
+        future: F,
+    ) -> Self {
+        Self {
+            key,
+            parent_key: Some(parent_key),
+            value: Some(value),
+            future: Some(future),
+        }
+    }
+    /// Access the value of this scope, return None after completed
+    pub fn value(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+}
+
+impl<F> ScopedFuture<PoolBumpAlloc, F>
+where
+    F: Future + Send + 'static,
+{
+    /// Store this Future in the Bump of the allocator it holds, so no extra allocation needed,
+    /// or on global heap when the Bump can not allocate.
+    /// It does not panic when the limit exceeded with [`LimitPolicy::Error`](crate::bump::pool::LimitPolicy::Error), so a request
+    /// does not take down the connection serving it, the Future is counted in `heap_fallbacks` instead
+    pub fn into_bumped(self) -> BumpFuture<F::Output> {
+        let alloc = self.value.as_ref().expect("ScopedFuture completed");
+        let (ptr, bump_ref) = match alloc.try_alloc_raw(Layout::new::<Self>()) {
+            Ok(Some(rslt)) => rslt,
+            Ok(None) => return self.bumped(&HeapAlloc),
+            Err(err) if err.kind() == AllocErrorKind::LimitExceeded => {
+                alloc.record_heap_fallback();
+                return self.bumped(&HeapAlloc);
+            }
+            Err(err) => handle_alloc_error(err.layout()),
+        };
+        let ptr = ptr.cast::<Self>();
+        // the allocator is moved into its own Bump, and dropped before bump_ref,
+        // so the Bump will not be reset until it dropped
+        let fut = unsafe {
+            ptr.as_ptr().write(self);
+            BumpBox::from_raw(ptr, Some(bump_ref))
+        };
+        BumpFuture::from_box(fut)
+    }
+}
+
+/// Scope of allocator associated with Future, implemented by the `Scope` type generated by
+/// [`alloc_mod!`](crate::alloc_mod), so adapters like tower Layer can be generic over it
+pub trait BumpScope: 'static {
+    /// Thread local slot where the allocator of current Future installed
+    fn key() -> &'static ScopeKey<PoolBumpAlloc>;
+    /// Take a Bump from pool for a new scope
+    fn new_alloc() -> PoolBumpAlloc;
+    /// Create a ConnectionBump owns one Bump of pool for a keep-alive connection
+    fn connection() -> ConnectionBump;
+    /// Call func inside a new scope, and convert the Future returned into BumpFuture
+    /// stored in the Bump of the scope, which is accessible when the Future polled
+    fn scoped<Func, F>(func: Func) -> BumpFuture<F::Output>
+    where
+        Func: FnOnce() -> F,
+        F: Future + Send + 'static,
+    {
+        Self::scoped_with(Self::new_alloc(), func)
+    }
+    /// Like [`BumpScope::scoped`], but the scope use the Bump of connection for the request generation
+    fn scoped_connection<Func, F>(conn: &ConnectionBump, func: Func) -> BumpFuture<F::Output>
+    where
+        Func: FnOnce() -> F,
+        F: Future + Send + 'static,
+    {
+        Self::scoped_with(PoolBumpAlloc::new(conn.take()), func)
+    }
+    /// Like [`BumpScope::scoped`], but the scope use the allocator input
+    fn scoped_with<Func, F>(alloc: PoolBumpAlloc, func: Func) -> BumpFuture<F::Output>
+    where
+        Func: FnOnce() -> F,
+        F: Future + Send + 'static,
+    {
+        let mut alloc = Some(alloc);
+        let fut = in_scope(Self::key(), None, &mut alloc, func);
+        let alloc = alloc.expect("allocator should be restored");
+        ScopedFuture::new(Self::key(), alloc, fut).into_bumped()
+    }
+}
+
+/// install value into slot, and restore the previous one when dropped even on panic
+struct Guard<T: 'static> {
+    key: &'static ScopeKey<T>,
+    prev: Option<NonNull<T>>,
+}
+impl<T: 'static> Guard<T> {
+    fn enter(key: &'static ScopeKey<T>, value: Option<NonNull<T>>) -> Self {
+        let prev = key.with(|slot| slot.value.replace(value));
+        Self { key, prev }
+    }
+}
+impl<T: 'static> Drop for Guard<T> {
+    fn drop(&mut self) {
+        self.key.with(|slot| slot.value.set(self.prev));
+    }
+}
+
+/// call func with value installed in key slot,
+/// and the value of enclosing scope installed in parent_key slot if any
+fn in_scope<T: 'static, R>(
+    key: &'static ScopeKey<T>,
+    parent_key: Option<&'static ScopeKey<T>>,
+    value: &mut Option<T>,
+    func: impl FnOnce() -> R,
+) -> R {
+    // value is not touched through the reference until the slot restored
+    let value = value.as_mut().map(NonNull::from);
+    match parent_key {
+        None => {
+            let _guard = Guard::enter(key, value);
+            func()
+        }
+        Some(parent_key) => {
+            // guards dropped in reverse order, so parent slot is restored last
+            let parent = key.with(|slot| slot.value.get());
+            let _parent_guard = Guard::enter(parent_key, parent);
+            let _guard = Guard::enter(key, value);
+            func()
+        }
+    }
+}
+
+impl<T: 'static, F> Future for ScopedFuture<T, F>
+where
+    F: Future,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: future is pinned and only dropped in place, key and value are never pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = &mut this.future;
+        let rslt = in_scope(this.key, this.parent_key, &mut this.value, || {
+            let pinned = future
+                .as_mut()
+                .expect("ScopedFuture polled after completion");
+            let rslt = unsafe { Pin::new_unchecked(pinned) }.poll(cx);
+            if rslt.is_ready() {
+                // drop the completed Future inside scope, objects it holds may access the value
+                *future = None;
+            }
+            rslt
+        });
+        if rslt.is_ready() {
+            this.value = None;
+        }
+        rslt
+    }
+}
+impl<T: 'static, F> Drop for ScopedFuture<T, F> {
+    fn drop(&mut self) {
+        if self.future.is_none() {
+            return;
+        }
+        // drop the Future inside scope, skip when thread local already destroyed
+        let available = |key: &'static ScopeKey<T>| key.try_with(|_slot| ()).is_ok();
+        let entered = available(self.key)
+            && match self.parent_key {
+                Some(parent_key) => available(parent_key),
+                None => true,
+            };
+        if entered {
+            let future = &mut self.future;
+            in_scope(self.key, self.parent_key, &mut self.value, || {
+                *future = None
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use crate::{
+        alloc::PoolBumpAlloc,
+        bump::pool::{BumpPool, LimitPolicy, PoolConfig},
+        util::{check_send, check_unpin},
+    };
+
+    use super::{with_scope, ScopeSlot, ScopedFuture};
+
+    thread_local! {
+        static VALUE: ScopeSlot<u32> = const { ScopeSlot::new() };
+        static PARENT: ScopeSlot<u32> = const { ScopeSlot::new() };
+        static ALLOC: ScopeSlot<PoolBumpAlloc> = const { ScopeSlot::new() };
+    }
+
+    fn current() -> Option<u32> {
+        with_scope(&VALUE, |value| *value)
+    }
+    fn parent() -> Option<u32> {
+        with_scope(&PARENT, |value| *value)
+    }
+
+    #[test]
+    fn test_scope_bounds() {
+        check_send::<ScopedFuture<u32, std::future::Ready<()>>>();
+        check_unpin::<ScopedFuture<u32, std::future::Ready<()>>>();
+    }
+
+    #[test]
+    fn test_nested_scope() {
+        let inner = ScopedFuture::new(&VALUE, 2, async move { current() });
+        let outer = ScopedFuture::new(&VALUE, 1, async move {
+            let before = current();
+            let inner = inner.await;
+            (before, inner, current())
+        });
+        let mut cx = Context::from_waker(Waker::noop());
+        let rslt = pin!(outer).poll(&mut cx);
+        assert_eq!(rslt, Poll::Ready((Some(1), Some(2), Some(1))));
+        // previous value restored
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn test_child_scope() {
+        let grandchild =
+            ScopedFuture::child(&VALUE, &PARENT, 3, async move { (current(), parent()) });
+        let child = ScopedFuture::child(&VALUE, &PARENT, 2, async move {
+            let before = (current(), parent());
+            let grandchild = grandchild.await;
+            (before, grandchild, (current(), parent()))
+        });
+        let outer = ScopedFuture::new(&VALUE, 1, async move {
+            let child = child.await;
+            (child, (current(), parent()))
+        });
+        let mut cx = Context::from_waker(Waker::noop());
+        let rslt = pin!(outer).poll(&mut cx);
+        let expected = (
+            ((Some(2), Some(1)), (Some(3), Some(2)), (Some(2), Some(1))),
+            (Some(1), None),
+        );
+        assert_eq!(rslt, Poll::Ready(expected));
+        assert_eq!((current(), parent()), (None, None));
+    }
+
+    #[test]
+    fn test_reentrant_scope() {
+        let outer = ScopedFuture::new(&VALUE, 1, async move {
+            with_scope(&VALUE, |value| {
+                // poll nested scope while accessing the value
+                let inner = ScopedFuture::new(&VALUE, 2, async move { current() });
+                let mut cx = Context::from_waker(Waker::noop());
+                let inner = pin!(inner).poll(&mut cx);
+                (*value, inner, current())
+            })
+        });
+        let mut cx = Context::from_waker(Waker::noop());
+        let rslt = pin!(outer).poll(&mut cx);
+        assert_eq!(rslt, Poll::Ready(Some((1, Poll::Ready(Some(2)), Some(1)))));
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn test_into_bumped_limit() {
+        let conf = PoolConfig::new(1, 1024)
+            .with_max_bump_bytes(Some(2048))
+            .with_limit_policy(LimitPolicy::Error);
+        let pool = BumpPool::with_config(&conf);
+        let buf = [1_u8; 4096];
+        let fut = async move { buf.len() };
+        let alloc = PoolBumpAlloc::new(pool.take());
+        // exceed the limit, allocated on heap instead of panic
+        let fut = ScopedFuture::new(&ALLOC, alloc, fut).into_bumped();
+        assert!(fut.is_boxed());
+        assert_eq!(pool.stats().heap_fallbacks, 1);
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(pin!(fut).poll(&mut cx), Poll::Ready(4096));
+        assert_eq!(pool.len(), 1);
+    }
+}
@@ -0,0 +1,225 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! hyper integration, [`bump_service_fn`] scopes every request in its own Bump,
+//! and the response future is a [`BumpFuture`] stored in that Bump
+//! ```
+//! use std::convert::Infallible;
+//!
+//! use bump_future::{alloc_mod, server::bump_service_fn};
+//!
+//! alloc_mod!(bump_alloc);
+//!
+//! let svc = bump_service_fn(bump_alloc::Scope, |_req: hyper::Request<hyper::body::Incoming>| async move {
+//!     Ok::<_, Infallible>(hyper::Response::new(String::from("hello")))
+//! });
+//! ```
+use std::{error::Error, fmt, future::Future, marker::PhantomData};
+
+use hyper::{
+    body::{Body, Incoming},
+    rt::{Read, Write},
+    server::conn::http1,
+    service::Service,
+    Request, Response,
+};
+
+use crate::{bump::connection::ConnectionBump, future::BumpFuture, scope::BumpScope};
+
+/// Create a hyper Service from function, every request is called inside a new scope of S,
+/// the scope is the mod generated by [`alloc_mod!`](crate::alloc_mod), e.g. `bump_alloc::Scope`
+pub fn bump_service_fn<S, F>(_scope: S, func: F) -> BumpServiceFn<S, F>
+where
+    S: BumpScope,
+{
+    BumpServiceFn {
+        func,
+        conn: None,
+        _p: PhantomData,
+    }
+}
+
+/// Serve a HTTP/1 connection with builder, requests of this connection are scoped in the Bump
+/// of a [`ConnectionBump`], which is reset after every request generation, see [`BumpScope::connection`]
+pub fn serve_connection<S, I, F, Fut, B, E>(
+    builder: &http1::Builder,
+    io: I,
+    scope: S,
+    func: F,
+) -> http1::Connection<I, BumpServiceFn<S, F>>
+where
+    S: BumpScope,
+    I: Read + Write + Unpin,
+    F: Fn(Request<Incoming>) -> Fut,
+    Fut: Future<Output = Result<Response<B>, E>> + Send + 'static,
+    B: Body + 'static,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    let svc = bump_service_fn(scope, func).with_connection(S::connection());
+    builder.serve_connection(io, svc)
+}
+
+/// Service returned by [`bump_service_fn`]
+pub struct BumpServiceFn<S, F> {
+    func: F,
+    conn: Option<ConnectionBump>,
+    _p: PhantomData<fn() -> S>,
+}
+impl<S, F> BumpServiceFn<S, F>
+where
+    S: BumpScope,
+{
+    /// Use the Bump of connection for requests instead of take one from pool every request
+    pub fn with_connection(mut self, conn: ConnectionBump) -> Self {
+        self.conn = Some(conn);
+        self
+    }
+    /// The ConnectionBump used for requests if any
+    pub fn connection(&self) -> Option<&ConnectionBump> {
+        self.conn.as_ref()
+    }
+}
+impl<S, F: Clone> Clone for BumpServiceFn<S, F> {
+    fn clone(&self) -> Self {
+        Self {
+            func: self.func.clone(),
+            conn: self.conn.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+impl<S, F> fmt::Debug for BumpServiceFn<S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BumpServiceFn")
+            .field("connection", &self.conn.is_some())
+            .finish()
+    }
+}
+impl<S, F, Req, Fut, R, E> Service<Req> for BumpServiceFn<S, F>
+where
+    S: BumpScope,
+    F: Fn(Req) -> Fut,
+    Fut: Future<Output = Result<R, E>> + Send + 'static,
+{
+    type Response = R;
+
+    type Error = E;
+
+    type Future = BumpFuture<Result<R, E>>;
+
+    fn call(&self, req: Req) -> Self::Future {
+        let func = &self.func;
+        match &self.conn {
+            Some(conn) => S::scoped_connection(conn, || func(req)),
+            None => S::scoped(|| func(req)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+    use hyper::{body::Incoming, server::conn::http1, service::Service, Request, Response};
+    use hyper_util::rt::TokioIo;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::{bump::pool::PoolConfig, future::BumpFutureExt};
+
+    use super::{bump_service_fn, serve_connection};
+
+    crate::alloc_mod!(server_alloc);
+    crate::alloc_mod!(call_alloc);
+
+    async fn hello(_req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+        // polled inside scope, allocator accessible
+        let body = server_alloc::with_task(|alloc| async move { "hello" }.bumped(alloc));
+        let body = body.expect("should be inside scope").await;
+        Ok(Response::new(Full::new(Bytes::from(body))))
+    }
+
+    /// send requests over one keep-alive connection, return when server closed it
+    async fn send_requests(addr: std::net::SocketAddr, count: usize) {
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, client) = hyper::client::conn::http1::handshake(TokioIo::new(tcp))
+            .await
+            .unwrap();
+        let client = tokio::spawn(client);
+        for _idx in 0..count {
+            let req = Request::new(Full::new(Bytes::new()));
+            sender.ready().await.unwrap();
+            let resp = sender.send_request(req).await.unwrap();
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(&body[..], b"hello");
+        }
+        drop(sender);
+        client.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_loopback_server() {
+        let conf = PoolConfig::new(4, 1024);
+        let _ = server_alloc::init(conf);
+        let pool = server_alloc::pool();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // every request take a Bump from pool
+        let takes = pool.stats().takes;
+        let server = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let svc = bump_service_fn(server_alloc::Scope, hello);
+            http1::Builder::new()
+                .serve_connection(TokioIo::new(tcp), svc)
+                .await
+                .unwrap();
+            listener
+        });
+        send_requests(addr, 8).await;
+        let listener = server.await.unwrap();
+        assert_eq!(pool.stats().takes, takes + 8);
+        assert_eq!(pool.len(), 4);
+
+        // requests of connection served by the Bump of connection
+        let takes = pool.stats().takes;
+        let server = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let builder = http1::Builder::new();
+            serve_connection(&builder, TokioIo::new(tcp), server_alloc::Scope, hello)
+                .await
+                .unwrap();
+        });
+        send_requests(addr, 8).await;
+        server.await.unwrap();
+        assert_eq!(pool.stats().takes, takes + 1);
+        assert_eq!(pool.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_service_future() {
+        let conf = PoolConfig::new(4, 1024);
+        let _ = call_alloc::init(conf);
+
+        // sync part of call runs inside scope, and response future stored in Bump
+        let svc = bump_service_fn(call_alloc::Scope, |req: u32| {
+            let in_scope = call_alloc::with_task(|_alloc| ()).is_some();
+            async move { Ok::<_, Infallible>((req, in_scope)) }
+        });
+        let fut = svc.call(1);
+        assert!(!fut.is_boxed());
+        assert_eq!(fut.await, Ok((1, true)));
+    }
+}
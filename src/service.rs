@@ -0,0 +1,202 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! tower [`Layer`] and [`Service`] adapters, convert the response future into [`BumpFuture`]
+//! stored in the Bump of a [`BumpScope`], so the Future type of Service is nameable
+//! ```
+//! use bump_future::{alloc_mod, service::BumpLayer};
+//!
+//! alloc_mod!(bump_alloc);
+//!
+//! let layer = BumpLayer::<bump_alloc::Scope>::new();
+//! ```
+use std::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    task::{Context, Poll},
+};
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{future::BumpFuture, scope::BumpScope};
+
+/// Layer wraps Service into [`BumpService`]
+pub struct BumpLayer<S> {
+    _p: PhantomData<fn() -> S>,
+}
+impl<S> BumpLayer<S>
+where
+    S: BumpScope,
+{
+    pub fn new() -> Self {
+        Self { _p: PhantomData }
+    }
+}
+impl<S> Default for BumpLayer<S>
+where
+    S: BumpScope,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<S> Clone for BumpLayer<S> {
+    fn clone(&self) -> Self {
+        Self { _p: PhantomData }
+    }
+}
+impl<S> fmt::Debug for BumpLayer<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BumpLayer").finish()
+    }
+}
+impl<S, Svc> Layer<Svc> for BumpLayer<S>
+where
+    S: BumpScope,
+{
+    type Service = BumpService<Svc, S>;
+
+    fn layer(&self, inner: Svc) -> Self::Service {
+        BumpService::new(inner)
+    }
+}
+
+/// Service runs `call` of inner Service inside a new scope of S,
+/// and the response future is stored in the Bump of the scope
+pub struct BumpService<Svc, S> {
+    inner: Svc,
+    _p: PhantomData<fn() -> S>,
+}
+impl<Svc, S> BumpService<Svc, S>
+where
+    S: BumpScope,
+{
+    pub fn new(inner: Svc) -> Self {
+        Self {
+            inner,
+            _p: PhantomData,
+        }
+    }
+    pub fn get_ref(&self) -> &Svc {
+        &self.inner
+    }
+    pub fn into_inner(self) -> Svc {
+        self.inner
+    }
+}
+impl<Svc: Clone, S> Clone for BumpService<Svc, S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+impl<Svc: fmt::Debug, S> fmt::Debug for BumpService<Svc, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BumpService")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+impl<Svc, S, Req> Service<Req> for BumpService<Svc, S>
+where
+    Svc: Service<Req>,
+    Svc::Future: Send + 'static,
+    S: BumpScope,
+{
+    type Response = Svc::Response;
+
+    type Error = Svc::Error;
+
+    type Future = BumpFuture<Result<Svc::Response, Svc::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let inner = &mut self.inner;
+        S::scoped(|| inner.call(req))
+    }
+}
+
+/// Service extension trait for wrap Service into [`BumpService`]
+pub trait BumpServiceExt<Req>: Service<Req> + Sized {
+    /// Wrap self into BumpService of scope S, e.g. `svc.bumped::<bump_alloc::Scope>()`
+    fn bumped<S>(self) -> BumpService<Self, S>
+    where
+        S: BumpScope;
+}
+impl<Svc, Req> BumpServiceExt<Req> for Svc
+where
+    Svc: Service<Req>,
+    Svc::Future: Future + Send + 'static,
+{
+    fn bumped<S>(self) -> BumpService<Self, S>
+    where
+        S: BumpScope,
+    {
+        BumpService::new(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use tower::{service_fn, Service, ServiceBuilder, ServiceExt};
+
+    use crate::{
+        bump::pool::PoolConfig,
+        future::{BumpFuture, BumpFutureExt},
+    };
+
+    use super::{BumpLayer, BumpServiceExt};
+
+    crate::alloc_mod!(tower_alloc);
+
+    async fn handle(req: u32) -> Result<u32, Infallible> {
+        // polled inside scope, allocator accessible
+        let fut = tower_alloc::with_task(|alloc| async move { req * 2 }.bumped(alloc));
+        Ok(fut.expect("should be inside scope").await)
+    }
+
+    #[tokio::test]
+    async fn test_bump_layer() {
+        let conf = PoolConfig::new(2, 1024);
+        let _ = tower_alloc::init(conf);
+        let pool = tower_alloc::pool();
+
+        let mut svc = ServiceBuilder::new()
+            .layer(BumpLayer::<tower_alloc::Scope>::new())
+            .service_fn(handle);
+        // response future is nameable, and stored in the Bump of scope
+        let fut: BumpFuture<Result<u32, Infallible>> = svc.ready().await.unwrap().call(16);
+        assert!(!fut.is_boxed());
+        assert_eq!(pool.len(), 1);
+        assert_eq!(fut.await, Ok(32));
+        // Bump released after response future completed
+        assert_eq!(pool.len(), 2);
+
+        // sync part of call also runs inside scope
+        let svc = service_fn(|req: u32| {
+            let in_scope = tower_alloc::with_task(|_alloc| ()).is_some();
+            async move { Ok::<_, Infallible>((req, in_scope)) }
+        })
+        .bumped::<tower_alloc::Scope>();
+        assert_eq!(svc.oneshot(1).await, Ok((1, true)));
+        assert_eq!(pool.len(), 2);
+    }
+}
@@ -0,0 +1,148 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [`BumpStream<T>`] type
+//!
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+
+use crate::{alloc::BumpAlloc, boxed::BumpBox};
+
+/// Type erased Stream,stored in Bump
+/// It wraps a `BumpBox<dyn Stream + Send>`, polled through the vtable
+pub struct BumpStream<T> {
+    // pinned once wrapped, never moved out, BumpBox is a pointer, so Self is Unpin
+    inner: BumpBox<dyn Stream<Item = T> + Send>,
+}
+impl<T> BumpStream<T> {
+    /// Check if the Stream is allocated on global heap
+    #[inline]
+    pub fn is_boxed(&self) -> bool {
+        self.inner.is_boxed()
+    }
+}
+impl<T> From<BumpBox<dyn Stream<Item = T> + Send>> for BumpStream<T> {
+    fn from(stream: BumpBox<dyn Stream<Item = T> + Send>) -> Self {
+        Self { inner: stream }
+    }
+}
+impl<T> Stream for BumpStream<T> {
+    type Item = T;
+
+    #[inline]
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // Safety: the Stream is never moved out of BumpBox, same as Pin<BumpBox<_>>
+        unsafe { Pin::new_unchecked(&mut *self.inner) }.poll_next(cx)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// Stream extension trait for convert type impl Stream into BumpStream
+pub trait BumpStreamExt<T> {
+    /// take a BumpAlloc impl reference as input,and will convert self into BumpStream
+    fn bumped_stream<A>(self, alloc: &A) -> BumpStream<T>
+    where
+        A: BumpAlloc;
+}
+impl<S, T> BumpStreamExt<T> for S
+where
+    S: Stream<Item = T> + Send + 'static,
+{
+    fn bumped_stream<A>(self, alloc: &A) -> BumpStream<T>
+    where
+        A: BumpAlloc,
+    {
+        let stream = alloc.alloc_typed(self);
+        crate::unsize_bump_box!(stream, dyn Stream<Item = T> + Send).into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use futures_core::Stream;
+
+    use crate::{
+        alloc::PoolBumpAlloc,
+        bump::pool::BumpPool,
+        util::{check_send, check_unpin_stream},
+    };
+
+    use super::{BumpStream, BumpStreamExt};
+
+    #[test]
+    fn test_stream_bounds() {
+        //ensure BumpStream is Send
+        check_send::<BumpStream<()>>();
+
+        //ensure BumpStream is Unpin
+        check_unpin_stream::<BumpStream<()>>();
+    }
+
+    // yield 0..count,and is !Unpin
+    struct Counter {
+        next: u32,
+        count: u32,
+        _pin: std::marker::PhantomPinned,
+    }
+    impl Stream for Counter {
+        type Item = u32;
+
+        fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<u32>> {
+            let this = unsafe { self.get_unchecked_mut() };
+            if this.next == this.count {
+                return Poll::Ready(None);
+            }
+            this.next += 1;
+            Poll::Ready(Some(this.next - 1))
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            let remain = (self.count - self.next) as usize;
+            (remain, Some(remain))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bump_stream() {
+        let pool = BumpPool::new(1, 1024);
+        let alloc = PoolBumpAlloc::new(pool.take());
+        let mut stream = Counter {
+            next: 0,
+            count: 3,
+            _pin: std::marker::PhantomPinned,
+        }
+        .bumped_stream(&alloc);
+        drop(alloc);
+
+        assert_eq!(stream.size_hint(), (3, Some(3)));
+        let mut items = vec![];
+        while let Some(item) = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await
+        {
+            items.push(item);
+        }
+        assert_eq!(items, vec![0, 1, 2]);
+        assert_eq!(stream.size_hint(), (0, Some(0)));
+    }
+}